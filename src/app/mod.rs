@@ -1,8 +1,11 @@
 mod states;
 
-use eframe::egui::{self, Ui};
+use crate::storage::Storage;
+
+use eframe::egui;
 pub enum StateResult {
     Noop,
+    #[allow(dead_code, reason = "MegaSearch is the only screen so far")]
     Change(Box<dyn State>),
 }
 pub trait State {
@@ -11,20 +14,20 @@ pub trait State {
 
 pub struct App {
     state: Box<dyn State>,
+    #[allow(dead_code, reason = "nothing goes back to an earlier screen yet")]
     state_history: Vec<Box<dyn State>>,
 }
 impl App {
-    pub fn run() {
+    pub fn run(storage: Storage) {
         let app = App {
             state_history: vec![],
-            state: Box::new(states::MegaSearch::new()),
+            state: Box::new(states::MegaSearch::new(storage)),
         };
         main(app).unwrap();
     }
 }
 
 fn main(app: App) -> eframe::Result {
-    env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default(),
         ..Default::default()
@@ -1,6 +1,10 @@
 use super::{State, StateResult};
 use crate::{
-    crawler::{CrawlerResult, CrawlerTask},
+    color_identity::ColorIdentity,
+    crawler::{CardNumber, ComboQuery, CrawlerResult, CrawlerTask, Format},
+    outcome::{self, Outcome},
+    sources::{ComboSource, LocalDatabase, Spellbook},
+    storage::Storage,
     Color,
 };
 use eframe::egui::{self};
@@ -10,16 +14,45 @@ pub struct MegaSearch {
     cards: Vec<(String, bool, bool)>,
     tasks: Vec<(String, CrawlerTask)>,
     results: std::collections::HashMap<String, CrawlerResult>,
-    color_checkboxes: std::collections::HashMap<Color, bool>,
+    /// Why searches or their sources failed, shown until cleared.
+    errors: Vec<String>,
+    colors: ColorIdentity,
+    /// Searches for colorless combos when no colors are selected, instead of every identity.
+    colorless: bool,
+    format: Option<Format>,
+    /// Text form of the card count, e.g. "2..=4". Empty allows any number of cards.
+    card_number: String,
+    /// Cards that no search should return combos with, e.g. house-banned cards.
+    excluded_cards: Vec<String>,
+    outcome_checkboxes: Vec<(Outcome, bool)>,
+    use_local_db: bool,
+    /// Checks every cached page with the site instead of using unexpired copies.
+    force_refresh: bool,
+    /// Searches only cached pages and imported data, never the network.
+    offline: bool,
+    storage: Storage,
 }
 impl MegaSearch {
-    pub fn new() -> Self {
+    pub fn new(storage: Storage) -> Self {
         Self {
             search: String::default(),
             cards: vec![],
             tasks: vec![],
             results: std::collections::HashMap::new(),
-            color_checkboxes: Color::check_list(),
+            errors: vec![],
+            colors: ColorIdentity::COLORLESS,
+            colorless: false,
+            format: Some(Format::Commander),
+            card_number: String::default(),
+            excluded_cards: vec![],
+            outcome_checkboxes: Outcome::all()
+                .into_iter()
+                .map(|outcome| (outcome, false))
+                .collect(),
+            use_local_db: false,
+            force_refresh: false,
+            offline: false,
+            storage,
         }
     }
 }
@@ -37,25 +70,45 @@ impl MegaSearch {
             })
             .collect()
     }
-    fn selected_colors(&self) -> Vec<Color> {
-        self.color_checkboxes
-            .iter()
-            .filter_map(|(color, selected)| if *selected { Some(*color) } else { None })
-            .collect()
+    fn selected_colors(&self) -> Option<ColorIdentity> {
+        if self.colorless || !self.colors.is_colorless() {
+            Some(self.colors)
+        } else {
+            None
+        }
     }
 
     fn add_card(&mut self, card: String) {
-        let card_name = if card.is_empty() {
-            None
+        let required_cards = if card.is_empty() {
+            vec![]
+        } else {
+            vec![card.clone()]
+        };
+        let query = ComboQuery {
+            colors: self.selected_colors(),
+            required_cards,
+            excluded_cards: self.excluded_cards.clone(),
+            format: self.format,
+            card_number: self.card_number.parse().unwrap_or_default(),
+            outcomes: self
+                .outcome_checkboxes
+                .iter()
+                .filter_map(|(outcome, selected)| if *selected { Some(*outcome) } else { None })
+                .collect(),
+            ..Default::default()
+        };
+        let sources: Vec<Box<dyn ComboSource>> = if self.use_local_db {
+            vec![Box::new(LocalDatabase)]
+        } else {
+            vec![Box::new(Spellbook::new(crate::crawler::Backend::Api))]
+        };
+        let task = if self.offline {
+            CrawlerTask::offline(query, sources, self.storage.clone())
+        } else if self.force_refresh {
+            CrawlerTask::refreshing(query, sources, self.storage.clone())
         } else {
-            Some(card.clone())
+            CrawlerTask::new(query, sources, self.storage.clone())
         };
-        let task = CrawlerTask::new(
-            self.selected_colors(),
-            card_name,
-            Some(crate::crawler::Format::Commander),
-            crate::crawler::CardNumber::None,
-        );
         self.tasks.push((card.clone(), task));
         self.cards.push((card.clone(), false, false));
         self.cards.sort();
@@ -63,25 +116,74 @@ impl MegaSearch {
 
     fn recalculate_matching_combos(&mut self) {}
 
-    fn render_search_box(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+    fn render_search_box(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
         ui.horizontal(|ui| {
-            for (color, selected) in self.color_checkboxes.iter_mut() {
-                let color_name = match color {
-                    Color::White => "White",
-                    Color::Blue => "Blue",
-                    Color::Black => "Black",
-                    Color::Red => "Red",
-                    Color::Green => "Green",
-                    Color::Colorless => "Colorless",
-                };
-                ui.checkbox(selected, color_name);
+            for color in Color::all() {
+                let mut selected = self.colors.contains(color);
+                if ui.checkbox(&mut selected, color.name()).changed() {
+                    self.colors.set(color, selected);
+                    self.colorless = false;
+                }
+            }
+            if ui.checkbox(&mut self.colorless, "Colorless").changed() && self.colorless {
+                self.colors = ColorIdentity::COLORLESS;
+            }
+            match self.selected_colors() {
+                Some(colors) => ui.label(colors.name()),
+                None => ui.label("Any colors"),
+            };
+        });
+        egui::ComboBox::from_label("Format")
+            .selected_text(self.format.map(|format| format.name()).unwrap_or("Any"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.format, None, "Any");
+                for format in Format::all() {
+                    ui.selectable_value(&mut self.format, Some(format), format.name())
+                        .on_hover_text(format!(
+                            "{} cards{}",
+                            format.deck_size(),
+                            if format.uses_commander() {
+                                ", with a commander"
+                            } else {
+                                ""
+                            }
+                        ));
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.label("Cards: ");
+            ui.text_edit_singleline(&mut self.card_number)
+                .on_hover_text(
+                    "A count such as 3, a comparison such as <=3 or a range such as 2..=4",
+                );
+            if let Err(e) = self.card_number.parse::<CardNumber>() {
+                ui.colored_label(egui::Color32::RED, e);
             }
         });
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Outcomes: ");
+            for (outcome, selected) in self.outcome_checkboxes.iter_mut() {
+                ui.checkbox(selected, outcome.name());
+            }
+        });
+        ui.checkbox(&mut self.use_local_db, "Imported data only")
+            .on_hover_text("Search the imported bulk data instead of Commander Spellbook");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.offline, "Offline").on_hover_text(
+                "Only search cached pages and imported data, pages that aren't cached are skipped",
+            );
+            ui.add_enabled(
+                !self.offline,
+                egui::Checkbox::new(&mut self.force_refresh, "Force refresh"),
+            )
+            .on_hover_text("Check every cached page for changes, even ones that haven't expired");
+        });
         ui.horizontal(|ui| {
             ui.label("Search: ");
             ui.text_edit_singleline(&mut self.search);
+            let valid = self.card_number.parse::<CardNumber>().is_ok();
             if ui
-                .button("Add Card")
+                .add_enabled(valid, egui::Button::new("Add Card"))
                 .on_hover_text("Add card to search")
                 .clicked()
             {
@@ -89,12 +191,47 @@ impl MegaSearch {
                 self.add_card(self.search.clone());
                 self.search = String::default();
             }
+            if ui
+                .button("Exclude Card")
+                .on_hover_text("Never show combos with this card")
+                .clicked()
+                && !self.search.trim().is_empty()
+            {
+                self.excluded_cards.push(self.search.trim().to_string());
+                self.search = String::default();
+            }
         });
+        if !self.excluded_cards.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Excluded: ");
+                let mut cards_to_keep = vec![];
+                for card in self.excluded_cards.drain(..) {
+                    if !ui.button(format!("{} x", card)).clicked() {
+                        cards_to_keep.push(card);
+                    }
+                }
+                self.excluded_cards = cards_to_keep;
+            });
+        }
 
-        ui.label(&format!("Background searches: {}", self.tasks.len()));
+        let found: usize = self.tasks.iter().map(|(_, task)| task.combos_found()).sum();
+        ui.label(format!(
+            "Background searches: {} ({} combos found so far)",
+            self.tasks.len(),
+            found
+        ));
+
+        if !self.errors.is_empty() {
+            for error in self.errors.iter() {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            if ui.button("Clear errors").clicked() {
+                self.errors.clear();
+            }
+        }
     }
 
-    fn render_combo_selector(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+    fn render_combo_selector(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
         ui.horizontal(|ui| {
             if ui.button("Clear selected combo cards").clicked() {
                 self.cards
@@ -119,7 +256,7 @@ impl MegaSearch {
                     cards_to_remove.push(card.clone());
                 }
 
-                ui.label(format!("{}", card));
+                ui.label(card.as_str());
             });
         }
 
@@ -134,7 +271,7 @@ impl MegaSearch {
         }
     }
 
-    fn render_combos(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+    fn render_combos(&mut self, ui: &mut egui::Ui, _ctx: &egui::Context) {
         let selected_cards = self.selected_cards();
         let mut cards_to_add = vec![];
 
@@ -153,6 +290,37 @@ impl MegaSearch {
                     });
                 }
             });
+            let heading = if result.is_complete() {
+                format!("{} combos", name)
+            } else {
+                format!("{} combos (partial)", name)
+            };
+            let response = ui.collapsing(heading, |ui| {
+                for (category, combos) in outcome::group_by_outcome(&result.combos) {
+                    let category = category.map(|outcome| outcome.name()).unwrap_or("Other");
+                    ui.collapsing(format!("{} ({})", category, combos.len()), |ui| {
+                        for combo in combos {
+                            ui.horizontal(|ui| {
+                                let label = ui
+                                    .label(combo.cards.join(", "))
+                                    .on_hover_text(combo.to_string());
+                                if !combo.results.is_empty() {
+                                    ui.weak(combo.results.join(", ")).labelled_by(label.id);
+                                }
+                                if let Some(url) = combo.url.as_ref() {
+                                    ui.hyperlink_to("View", url);
+                                }
+                            });
+                        }
+                    });
+                }
+            });
+            if !result.is_complete() {
+                response.header_response.on_hover_text(format!(
+                    "Missing combos from {}, see the errors above",
+                    result.failed_sources.join(", ")
+                ));
+            }
         }
 
         for card in cards_to_add {
@@ -166,11 +334,18 @@ impl State for MegaSearch {
         // Process tasks, removing them as they complete
         for i in (0..self.tasks.len()).rev() {
             self.tasks[i].1.update();
-            if self.tasks[i].1.result.is_some() {
-                let name = self.tasks[i].0.clone();
-                let result = self.tasks[i].1.result.take().unwrap();
-                self.results.insert(name, result);
-                self.tasks.remove(i);
+            if self.tasks[i].1.is_finished() {
+                let (name, mut task) = self.tasks.remove(i);
+                for (source, error) in task.failures.iter() {
+                    self.errors
+                        .push(format!("{}: {} failed: {}", name, source, error));
+                }
+                if let Some(error) = task.error.take() {
+                    self.errors.push(format!("{}: {}", name, error));
+                }
+                if let Some(result) = task.result.take() {
+                    self.results.insert(name, result);
+                }
             }
         }
 
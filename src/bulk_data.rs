@@ -0,0 +1,77 @@
+use crate::{
+    combo::Combo,
+    spellbook_api::Variant,
+    storage::{Storage, StoredCombo},
+};
+use serde::Deserialize;
+use std::path::Path;
+
+/// The bulk variants export published by Commander Spellbook.
+#[derive(Debug, Deserialize)]
+pub struct BulkExport {
+    pub variants: Vec<Variant>,
+}
+
+/// Reads the combos from a downloaded bulk variants file.
+pub fn read(path: &Path) -> Result<Vec<StoredCombo>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let export: BulkExport = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| format!("Unable to parse {}: {e}", path.display()))?;
+
+    Ok(export
+        .variants
+        .iter()
+        .map(|variant| {
+            let combo = Combo::from_variant(variant);
+            StoredCombo {
+                legal_formats: combo.legal_formats.clone(),
+                combo,
+                popularity: variant.popularity,
+            }
+        })
+        .collect())
+}
+
+/// Loads a downloaded bulk variants file into the combo tables, replacing any
+/// combos with the same id. Returns the number of combos imported.
+pub fn import(path: &Path, storage: &Storage) -> Result<usize, String> {
+    let combos = read(path)?;
+    storage.import_combos(&combos).map_err(|e| e.to_string())?;
+
+    Ok(combos.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_bulk_export() {
+        let storage = Storage::open_in_memory().unwrap();
+        let path = Path::new("tests/fixtures/spellbook_bulk_variants.json");
+
+        assert_eq!(4, import(path, &storage).unwrap());
+        // Importing again replaces rather than duplicates
+        assert_eq!(4, import(path, &storage).unwrap());
+        assert_eq!(4, storage.find_combos(&Default::default()).unwrap().len());
+
+        let combo = storage.read_combo("2117-4321-5555").unwrap().unwrap();
+        assert_eq!(
+            vec![
+                "Incubation Druid",
+                "Staff of Domination",
+                "Nyxbloom Ancient"
+            ],
+            combo.cards
+        );
+        assert_eq!(
+            vec!["Infinite colored mana", "Infinite card draw"],
+            combo.results
+        );
+        assert_eq!(3, combo.steps.len());
+        assert_eq!(
+            Some("G".to_string()),
+            combo.color_identity.map(|identity| identity.to_string())
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{
+    error::CrawlError,
+    query::Query,
+    storage::{now, PageSummary, SearchSummary, Storage},
+    web_page::{self, WebPage},
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The archive format `export` writes. Bump it when the layout changes.
+const ARCHIVE_VERSION: u32 = 1;
+
+/// Which cached pages and saved searches a cache command applies to. An empty filter
+/// matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct CacheFilter {
+    /// Pages whose url starts with this. Saved searches don't have a url, so none match.
+    pub url_prefix: Option<String>,
+    /// Pages searching for this query, and the searches saved for it. Queries match however
+    /// they're written or encoded, e.g. `ci:c` matches `q=ci%3A%22c%22`.
+    pub query: Option<Query>,
+    /// Pages fetched and searches saved before this time, in seconds since the Unix epoch.
+    pub before: Option<i64>,
+    /// Only pages that fail their checks, e.g. error pages cached before pages were checked.
+    /// Saved searches aren't checked, so none match.
+    pub failing_checks: bool,
+}
+impl CacheFilter {
+    pub fn matches_page(&self, page: &PageSummary) -> bool {
+        if let Some(prefix) = self.url_prefix.as_deref() {
+            if !page.url.starts_with(prefix) {
+                return false;
+            }
+        }
+        if let Some(query) = self.query.as_ref() {
+            if url_query(&page.url).as_ref() != Some(query) {
+                return false;
+            }
+        }
+        // Pages cached before fetch times were recorded count as old
+        self.before
+            .is_none_or(|before| page.fetched_at.is_none_or(|fetched_at| fetched_at < before))
+    }
+
+    pub fn matches_search(&self, search: &SearchSummary) -> bool {
+        if self.url_prefix.is_some() || self.failing_checks {
+            return false;
+        }
+        if let Some(query) = self.query.as_ref() {
+            let (encoded, _) = search.key.split_once('|').unwrap_or((&search.key, ""));
+            if parse_query(&url_escape::decode(encoded)).as_ref() != Some(query) {
+                return false;
+            }
+        }
+        self.before.is_none_or(|before| search.searched_at < before)
+    }
+}
+
+/// Parses a query in the site's text syntax, where an empty one lists every combo.
+pub fn parse_query(text: &str) -> Option<Query> {
+    match text.trim() {
+        "" => Some(Query::And(vec![])),
+        text => text.parse().ok(),
+    }
+}
+
+/// The query a Commander Spellbook search or API url asks for.
+fn url_query(url: &str) -> Option<Query> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let (_, q) = url.query_pairs().find(|(key, _)| key == "q")?;
+    parse_query(&q)
+}
+
+/// Cached pages matching `filter`, by url.
+pub fn list(storage: &Storage, filter: &CacheFilter) -> Result<Vec<PageSummary>, CrawlError> {
+    let mut pages: Vec<PageSummary> = storage
+        .page_summaries()?
+        .into_iter()
+        .filter(|page| filter.matches_page(page))
+        .collect();
+    if filter.failing_checks {
+        // Checking a page needs its body, which summaries leave out
+        let ids: Vec<i32> = pages.iter().map(|page| page.id).collect();
+        let failing: Vec<i32> = storage
+            .pages_by_id(&ids)?
+            .into_iter()
+            .filter(|page| web_page::check_for(&page.url)(page).is_err())
+            .map(|page| page.id)
+            .collect();
+        pages.retain(|page| failing.contains(&page.id));
+    }
+    pages.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(pages)
+}
+
+/// Deletes the pages and saved searches matching `filter`, unless `dry_run` is set.
+/// Returns how many of each there were.
+pub fn purge(
+    storage: &Storage,
+    filter: &CacheFilter,
+    dry_run: bool,
+) -> Result<(usize, usize), CrawlError> {
+    let pages = list(storage, filter)?;
+    let searches: Vec<SearchSummary> = storage
+        .searches()?
+        .into_iter()
+        .filter(|search| filter.matches_search(search))
+        .collect();
+    if !dry_run {
+        for page in pages.iter() {
+            storage.delete_page(page.id)?;
+        }
+        for search in searches.iter() {
+            storage.delete_search(search.id)?;
+        }
+    }
+    Ok((pages.len(), searches.len()))
+}
+
+/// A cached page as it's written to an archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ArchivedPage {
+    url: String,
+    body: String,
+    status: Option<u16>,
+    content_type: Option<String>,
+    fetched_at: Option<i64>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// A portable copy of the page cache, so that a teammate can start with a warm cache.
+/// It's the same whichever database it came from.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheArchive {
+    version: u32,
+    exported_at: i64,
+    pages: Vec<ArchivedPage>,
+}
+
+/// Writes the pages matching `filter` to a JSON archive at `path`. Returns how many there were.
+pub fn export(storage: &Storage, filter: &CacheFilter, path: &Path) -> Result<usize, CrawlError> {
+    let wanted: Vec<i32> = list(storage, filter)?.iter().map(|page| page.id).collect();
+    // Only the bodies of the pages being exported are read
+    let pages: Vec<ArchivedPage> = storage
+        .pages_by_id(&wanted)?
+        .into_iter()
+        .map(|page| ArchivedPage {
+            url: page.url,
+            body: page.html_body,
+            status: page.status,
+            content_type: page.content_type,
+            fetched_at: page.fetched_at,
+            etag: page.etag,
+            last_modified: page.last_modified,
+        })
+        .collect();
+    let archive = CacheArchive {
+        version: ARCHIVE_VERSION,
+        exported_at: now(),
+        pages,
+    };
+
+    let io_error = |message: String| CrawlError::Io {
+        path: path.display().to_string(),
+        message,
+    };
+    let file = std::fs::File::create(path).map_err(|e| io_error(e.to_string()))?;
+    serde_json::to_writer(std::io::BufWriter::new(file), &archive)
+        .map_err(|e| io_error(e.to_string()))?;
+    Ok(archive.pages.len())
+}
+
+/// Adds the pages from an archive written by `export` to the cache. Pages that are already
+/// cached are only replaced by newer copies, and pages failing their checks are skipped.
+/// Returns how many pages were added and how many were skipped.
+pub fn import(storage: &Storage, path: &Path) -> Result<(usize, usize), CrawlError> {
+    let file = std::fs::File::open(path).map_err(|e| CrawlError::Io {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    let archive: CacheArchive =
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| CrawlError::Parse {
+            source: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+    if archive.version > ARCHIVE_VERSION {
+        return Err(CrawlError::Parse {
+            source: path.display().to_string(),
+            message: format!(
+                "archive version {} is newer than this build's {ARCHIVE_VERSION}",
+                archive.version
+            ),
+        });
+    }
+
+    let (mut added, mut skipped) = (0, 0);
+    for archived in archive.pages {
+        let page = WebPage {
+            id: 0,
+            url: archived.url,
+            html_body: archived.body,
+            status: archived.status,
+            content_type: archived.content_type,
+            fetched_at: archived.fetched_at,
+            etag: archived.etag,
+            last_modified: archived.last_modified,
+        };
+        let newer = match storage.cached_page(&page.url)? {
+            Some(cached) => page.fetched_at > cached.fetched_at,
+            None => true,
+        };
+        if newer && web_page::check_for(&page.url)(&page).is_ok() {
+            storage.store_page(&page)?;
+            added += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+    Ok((added, skipped))
+}
+
+/// How long ago `time` was, roughly, e.g. "3d" or "5h".
+pub fn format_age(time: Option<i64>) -> String {
+    let Some(time) = time else {
+        return "unknown".to_string();
+    };
+    let seconds = now().saturating_sub(time).max(0);
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// A size in bytes for people, e.g. "1.5 MB".
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{combo::Combo, crawler::ComboQuery, saved_search, storage::compress_body};
+
+    fn page(url: &str, fetched_at: i64) -> WebPage {
+        WebPage {
+            id: 0,
+            url: url.to_string(),
+            html_body: "<p>Commander Spellbook</p>".to_string(),
+            status: Some(200),
+            content_type: Some("text/html".to_string()),
+            fetched_at: Some(fetched_at),
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    /// A cache with a search saved for `ci:c` and pages for it and a few others.
+    fn cache() -> Storage {
+        let storage = Storage::open_in_memory().unwrap();
+        for (url, fetched_at) in [
+            (
+                "https://commanderspellbook.com/search/?q=ci%3A%22c%22&page=1",
+                1000,
+            ),
+            (
+                "https://commanderspellbook.com/search/?q=ci%3Ac&page=2",
+                1000,
+            ),
+            (
+                "https://commanderspellbook.com/search/?q=card%3ASol&page=1",
+                5000,
+            ),
+            ("https://edhrec.com/combos/sol-ring", 9000),
+        ] {
+            storage.store_page(&page(url, fetched_at)).unwrap();
+        }
+        let query = ComboQuery {
+            colors: Some(crate::color_identity::ColorIdentity::COLORLESS),
+            ..Default::default()
+        };
+        let combos = vec![Combo {
+            id: Some("1414-5250".to_string()),
+            ..Combo::new(vec!["Basalt Monolith".to_string()])
+        }];
+        storage
+            .save_search(
+                &saved_search::search_key(&query, &["commanderspellbook"]),
+                &combos,
+            )
+            .unwrap();
+        storage
+    }
+
+    #[test]
+    fn purges_by_prefix_query_and_age() {
+        let storage = cache();
+        let filter =
+            |url_prefix: Option<&str>, query: Option<&str>, before: Option<i64>| CacheFilter {
+                url_prefix: url_prefix.map(|prefix| prefix.to_string()),
+                query: query.map(|query| parse_query(query).unwrap()),
+                before,
+                ..Default::default()
+            };
+
+        assert_eq!(4, list(&storage, &CacheFilter::default()).unwrap().len());
+        assert_eq!(
+            (1, 0),
+            purge(
+                &storage,
+                &filter(Some("https://edhrec.com/"), None, None),
+                true
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            (2, 0),
+            purge(&storage, &filter(None, None, Some(2000)), true).unwrap()
+        );
+        // The query matches however the urls spell it, and the saved search goes too
+        assert_eq!(
+            (2, 1),
+            purge(&storage, &filter(None, Some("ci:c"), None), false).unwrap()
+        );
+        assert_eq!(2, list(&storage, &CacheFilter::default()).unwrap().len());
+        assert!(storage.searches().unwrap().is_empty());
+    }
+
+    #[test]
+    fn purges_pages_that_fail_their_checks() {
+        let storage = cache();
+        let bad = WebPage {
+            status: Some(500),
+            html_body: "<p>Internal Server Error</p>".to_string(),
+            ..page("https://edhrec.com/combos/mana-crypt", 9000)
+        };
+        storage.store_page(&bad).unwrap();
+        let failing = CacheFilter {
+            failing_checks: true,
+            ..Default::default()
+        };
+
+        let found = list(&storage, &failing).unwrap();
+        assert_eq!(
+            vec![bad.url.clone()],
+            found
+                .iter()
+                .map(|page| page.url.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!((1, 0), purge(&storage, &failing, false).unwrap());
+        assert!(storage.cached_page(&bad.url).unwrap().is_none());
+        assert_eq!(4, list(&storage, &CacheFilter::default()).unwrap().len());
+        assert_eq!(1, storage.searches().unwrap().len());
+    }
+
+    #[test]
+    fn reports_stats_and_hit_rate() {
+        let storage = cache();
+        storage.record_lookups(3, 1).unwrap();
+        storage.record_lookups(0, 0).unwrap();
+
+        let stats = storage.cache_stats().unwrap();
+        assert_eq!(4, stats.pages);
+        let stored_bytes = compress_body("<p>Commander Spellbook</p>").unwrap().len() as u64;
+        assert_eq!(4 * stored_bytes, stats.page_bytes);
+        assert_eq!(1, stats.combos);
+        assert_eq!(1, stats.searches);
+        assert_eq!(Some(0.75), stats.hit_rate());
+        assert!(stats.database_bytes > 0);
+
+        // Vacuuming leaves everything in place
+        storage.vacuum().unwrap();
+        assert_eq!(stats.pages, storage.cache_stats().unwrap().pages);
+    }
+
+    #[test]
+    fn exports_and_imports_archives() {
+        let path = std::env::temp_dir().join(format!("ccb-{}.json", uuid::Uuid::new_v4()));
+        let storage = cache();
+        let edhrec = CacheFilter {
+            url_prefix: Some("https://edhrec.com/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(1, export(&storage, &edhrec, &path).unwrap());
+        assert_eq!(4, export(&storage, &CacheFilter::default(), &path).unwrap());
+
+        let teammate = Storage::open_in_memory().unwrap();
+        let url = "https://edhrec.com/combos/sol-ring";
+        teammate.store_page(&page(url, 20000)).unwrap();
+        // Their own copy is newer, so it's kept
+        assert_eq!((3, 1), import(&teammate, &path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(4, teammate.pages().unwrap().len());
+        assert_eq!(
+            Some(20000),
+            teammate.cached_page(url).unwrap().unwrap().fetched_at
+        );
+    }
+}
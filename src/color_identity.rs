@@ -0,0 +1,217 @@
+use crate::Color;
+
+/// A set of colors, always kept in WUBRG order. The empty identity is colorless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct ColorIdentity(u8);
+
+/// Names for every identity, by bits in WUBRG order.
+const NAMES: [(u8, &str); 32] = [
+    (0b00000, "Colorless"),
+    (0b00001, "Mono-White"),
+    (0b00010, "Mono-Blue"),
+    (0b00100, "Mono-Black"),
+    (0b01000, "Mono-Red"),
+    (0b10000, "Mono-Green"),
+    (0b00011, "Azorius"),
+    (0b00110, "Dimir"),
+    (0b01100, "Rakdos"),
+    (0b11000, "Gruul"),
+    (0b10001, "Selesnya"),
+    (0b00101, "Orzhov"),
+    (0b01010, "Izzet"),
+    (0b10100, "Golgari"),
+    (0b01001, "Boros"),
+    (0b10010, "Simic"),
+    (0b10011, "Bant"),
+    (0b00111, "Esper"),
+    (0b01110, "Grixis"),
+    (0b11100, "Jund"),
+    (0b11001, "Naya"),
+    (0b10101, "Abzan"),
+    (0b01011, "Jeskai"),
+    (0b10110, "Sultai"),
+    (0b01101, "Mardu"),
+    (0b11010, "Temur"),
+    (0b01111, "Yore-Tiller"),
+    (0b11110, "Glint-Eye"),
+    (0b11101, "Dune-Brood"),
+    (0b11011, "Ink-Treader"),
+    (0b10111, "Witch-Maw"),
+    (0b11111, "Five-Color"),
+];
+
+impl ColorIdentity {
+    pub const COLORLESS: ColorIdentity = ColorIdentity(0);
+
+    pub fn from_colors(colors: impl IntoIterator<Item = Color>) -> Self {
+        let mut identity = Self::COLORLESS;
+        for color in colors {
+            identity.insert(color);
+        }
+        identity
+    }
+
+    fn bit(color: Color) -> u8 {
+        match color {
+            Color::White => 1 << 0,
+            Color::Blue => 1 << 1,
+            Color::Black => 1 << 2,
+            Color::Red => 1 << 3,
+            Color::Green => 1 << 4,
+        }
+    }
+
+    pub fn contains(&self, color: Color) -> bool {
+        self.0 & Self::bit(color) != 0
+    }
+
+    pub fn insert(&mut self, color: Color) {
+        self.0 |= Self::bit(color);
+    }
+
+    pub fn remove(&mut self, color: Color) {
+        self.0 &= !Self::bit(color);
+    }
+
+    pub fn set(&mut self, color: Color, selected: bool) {
+        if selected {
+            self.insert(color);
+        } else {
+            self.remove(color);
+        }
+    }
+
+    /// The colors in WUBRG order.
+    pub fn colors(&self) -> Vec<Color> {
+        Color::all()
+            .into_iter()
+            .filter(|color| self.contains(*color))
+            .collect()
+    }
+
+    pub fn is_colorless(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if every color of this identity is also in `other`, i.e. a
+    /// card of this identity can be played in a deck of the other.
+    pub fn is_subset_of(&self, other: ColorIdentity) -> bool {
+        self.0 & !other.0 == 0
+    }
+
+    pub fn is_superset_of(&self, other: ColorIdentity) -> bool {
+        other.is_subset_of(*self)
+    }
+
+    /// Lowercase mana symbols in WUBRG order, or "c" when colorless. This is the form Commander Spellbook searches on.
+    pub fn symbols(&self) -> String {
+        if self.is_colorless() {
+            return "c".to_string();
+        }
+        self.colors().iter().map(|color| color.symbol()).collect()
+    }
+
+    /// The guild, shard, wedge or other common name for this identity, e.g. "Esper".
+    pub fn name(&self) -> &'static str {
+        NAMES
+            .iter()
+            .find(|(bits, _)| *bits == self.0)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+}
+
+impl std::fmt::Display for ColorIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbols().to_uppercase())
+    }
+}
+
+impl std::str::FromStr for ColorIdentity {
+    type Err = String;
+
+    /// Parses mana symbols in any order, e.g. "wub" or "UBW", or a name such as "Esper" or "Sultai".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some((bits, _)) = NAMES.iter().find(|(_, name)| name.eq_ignore_ascii_case(s)) {
+            return Ok(ColorIdentity(*bits));
+        }
+
+        let mut identity = Self::COLORLESS;
+        for symbol in s.to_lowercase().chars() {
+            match Color::all().into_iter().find(|c| c.symbol() == symbol) {
+                Some(color) => identity.insert(color),
+                None if symbol == 'c' => {}
+                None => return Err(format!("Unknown color identity: {s}")),
+            }
+        }
+        Ok(identity)
+    }
+}
+
+/// Stored and exchanged as symbols, e.g. "WUB", the same way Commander Spellbook writes identities.
+impl serde::Serialize for ColorIdentity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ColorIdentity {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_symbols_and_names() {
+        let esper = ColorIdentity::from_colors([Color::White, Color::Blue, Color::Black]);
+        assert_eq!(Ok(esper), "wub".parse());
+        assert_eq!(Ok(esper), "BUW".parse());
+        assert_eq!(Ok(esper), "Esper".parse());
+        assert_eq!(
+            Ok(ColorIdentity::from_colors([
+                Color::Black,
+                Color::Green,
+                Color::Blue
+            ])),
+            "sultai".parse()
+        );
+        assert_eq!(Ok(ColorIdentity::COLORLESS), "C".parse());
+        assert!("wx".parse::<ColorIdentity>().is_err());
+    }
+
+    #[test]
+    fn wubrg_ordering() {
+        let abzan = ColorIdentity::from_colors([Color::Green, Color::Black, Color::White]);
+        assert_eq!("wbg", abzan.symbols());
+        assert_eq!("WBG", abzan.to_string());
+        assert_eq!(
+            vec![Color::White, Color::Black, Color::Green],
+            abzan.colors()
+        );
+        assert_eq!("Abzan", abzan.name());
+        assert_eq!("c", ColorIdentity::COLORLESS.symbols());
+    }
+
+    #[test]
+    fn subsets_and_supersets() {
+        let simic: ColorIdentity = "ug".parse().unwrap();
+        let temur: ColorIdentity = "Temur".parse().unwrap();
+        assert!(simic.is_subset_of(temur));
+        assert!(temur.is_superset_of(simic));
+        assert!(!temur.is_subset_of(simic));
+        assert!(ColorIdentity::COLORLESS.is_subset_of(simic));
+    }
+
+    #[test]
+    fn every_identity_has_a_name() {
+        for bits in 0..32u8 {
+            ColorIdentity(bits).name();
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    color_identity::ColorIdentity,
+    crawler::{Card, Format},
+    spellbook_api::Variant,
+};
+use serde::{Deserialize, Serialize};
+
+/// Where a card has to be for a combo to work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Zone {
+    Battlefield,
+    Hand,
+    Command,
+    Graveyard,
+    Library,
+    Exile,
+}
+impl Zone {
+    /// Parses the single letter zone codes used by Commander Spellbook.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "B" => Some(Zone::Battlefield),
+            "H" => Some(Zone::Hand),
+            "C" => Some(Zone::Command),
+            "G" => Some(Zone::Graveyard),
+            "L" => Some(Zone::Library),
+            "E" => Some(Zone::Exile),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Zone::Battlefield => "B",
+            Zone::Hand => "H",
+            Zone::Command => "C",
+            Zone::Graveyard => "G",
+            Zone::Library => "L",
+            Zone::Exile => "E",
+        }
+    }
+}
+
+/// The zones a card in a combo may start in. Any one of them is enough.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZoneRequirement {
+    pub card: Card,
+    pub zones: Vec<Zone>,
+    #[serde(default)]
+    pub must_be_commander: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Combo {
+    /// Id given by the source, if it has one.
+    pub id: Option<String>,
+    /// Page describing the combo.
+    pub url: Option<String>,
+    pub cards: Vec<Card>,
+    pub zones: Vec<ZoneRequirement>,
+    pub prerequisites: Vec<String>,
+    pub steps: Vec<String>,
+    /// What the combo produces, e.g. "Infinite mana" or "Win the game".
+    pub results: Vec<String>,
+    /// `None` when the source doesn't say, as most sources other than Commander Spellbook don't.
+    pub color_identity: Option<ColorIdentity>,
+    /// Names of the sources that reported this combo.
+    pub sources: Vec<String>,
+    /// Formats a source said the combo is legal in. Empty if none of them said.
+    #[serde(skip)]
+    pub legal_formats: Vec<Format>,
+}
+impl Combo {
+    /// A combo that only knows its cards.
+    pub fn new(cards: Vec<Card>) -> Self {
+        Self {
+            cards,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_variant(variant: &Variant) -> Self {
+        let zones = variant
+            .uses
+            .iter()
+            .map(|card_use| ZoneRequirement {
+                card: card_use.card.name.clone(),
+                zones: card_use
+                    .zone_locations
+                    .iter()
+                    .filter_map(|code| Zone::from_code(code))
+                    .collect(),
+                must_be_commander: card_use.must_be_commander,
+            })
+            .collect();
+
+        let mut prerequisites: Vec<String> = variant
+            .requires
+            .iter()
+            .map(|template_use| template_use.template.name.clone())
+            .collect();
+        prerequisites.extend(
+            [&variant.easy_prerequisites, &variant.notable_prerequisites]
+                .into_iter()
+                .flat_map(|text| text.lines())
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty()),
+        );
+
+        Self {
+            id: Some(variant.id.clone()),
+            url: Some(format!(
+                "https://commanderspellbook.com/combo/{}/",
+                variant.id
+            )),
+            cards: variant.card_names(),
+            zones,
+            prerequisites,
+            steps: parse_steps(&variant.description),
+            results: variant
+                .produces
+                .iter()
+                .map(|production| production.feature.name.clone())
+                .collect(),
+            color_identity: variant.identity.parse().ok(),
+            sources: vec![],
+            legal_formats: variant
+                .legalities
+                .iter()
+                .filter(|(_, legal)| **legal)
+                .filter_map(|(key, _)| Format::from_legality_key(key))
+                .collect(),
+        }
+    }
+
+    /// Adds what another source reported about the same cards. Its details only fill in
+    /// the ones this copy is missing, so it doesn't matter which source reported first.
+    pub fn merge(&mut self, other: Combo) {
+        self.id = self.id.take().or(other.id);
+        self.url = self.url.take().or(other.url);
+        for (field, other) in [
+            (&mut self.prerequisites, other.prerequisites),
+            (&mut self.steps, other.steps),
+            (&mut self.results, other.results),
+        ] {
+            if field.is_empty() {
+                *field = other;
+            }
+        }
+        if self.zones.is_empty() {
+            self.zones = other.zones;
+        }
+        self.color_identity = self.color_identity.or(other.color_identity);
+        for source in other.sources {
+            if !self.sources.contains(&source) {
+                self.sources.push(source);
+            }
+        }
+        for format in other.legal_formats {
+            if !self.legal_formats.contains(&format) {
+                self.legal_formats.push(format);
+            }
+        }
+    }
+}
+
+/// Writes the cards on the first line, followed by whatever else is known about the combo.
+impl std::fmt::Display for Combo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.cards.join(", "))?;
+
+        if !self.results.is_empty() {
+            write!(f, "\n    Results: {}", self.results.join(", "))?;
+        }
+        if let Some(identity) = self.color_identity {
+            write!(
+                f,
+                "\n    Color identity: {} ({})",
+                identity,
+                identity.name()
+            )?;
+        }
+        for requirement in self.zones.iter() {
+            let zones: Vec<&str> = requirement.zones.iter().map(|zone| zone.code()).collect();
+            write!(f, "\n    {} in: {}", requirement.card, zones.join("/"))?;
+        }
+        for prerequisite in self.prerequisites.iter() {
+            write!(f, "\n    Requires: {}", prerequisite)?;
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            write!(f, "\n    {}. {}", i + 1, step)?;
+        }
+        if let Some(url) = self.url.as_ref() {
+            write!(f, "\n    {}", url)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits a numbered description such as "1. Tap X.\n2. Untap X." into its steps.
+pub fn parse_steps(description: &str) -> Vec<String> {
+    description
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            match line.split_once(". ") {
+                Some((number, step)) if number.chars().all(|c| c.is_ascii_digit()) => {
+                    step.trim().to_string()
+                }
+                _ => line.to_string(),
+            }
+        })
+        .filter(|step| !step.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spellbook_api::VariantPage;
+
+    #[test]
+    fn combo_from_variant() {
+        let page: VariantPage = serde_json::from_str(include_str!(
+            "../tests/fixtures/spellbook_variants_page1.json"
+        ))
+        .unwrap();
+
+        let combo = Combo::from_variant(&page.results[0]);
+        assert_eq!(Some("1414-2730-5131-5256".to_string()), combo.id);
+        assert_eq!(
+            Some("https://commanderspellbook.com/combo/1414-2730-5131-5256/".to_string()),
+            combo.url
+        );
+        assert_eq!(vec![Zone::Battlefield], combo.zones[0].zones);
+        assert_eq!(
+            vec![
+                "All permanents on the battlefield.",
+                "Ability to pay {2} once."
+            ],
+            combo.prerequisites
+        );
+        assert_eq!(4, combo.steps.len());
+        assert_eq!("Pay {3} to untap Basalt Monolith.", combo.steps[1]);
+        assert_eq!(vec!["Infinite colorless mana"], combo.results);
+        assert_eq!(Some(ColorIdentity::COLORLESS), combo.color_identity);
+        assert!(combo.to_string().contains("Color identity: C (Colorless)"));
+    }
+
+    #[test]
+    fn unknown_identity_isnt_shown() {
+        let combo = Combo::new(vec![
+            "Basalt Monolith".to_string(),
+            "Forsaken Monument".to_string(),
+        ]);
+        assert_eq!(None, combo.color_identity);
+        assert_eq!("Basalt Monolith, Forsaken Monument", combo.to_string());
+    }
+}
@@ -1,89 +1,515 @@
-use crate::{web_page::WebPage, Color};
+use crate::{
+    color_identity::ColorIdentity,
+    combo::Combo,
+    error::CrawlError,
+    outcome::Outcome,
+    query::{Comparison, Query, Term},
+    saved_search,
+    sources::{ComboSource, SPELLBOOK_SEARCH_URL},
+    storage::Storage,
+    web_page::{self, CacheMode},
+};
 use std::{
     collections::HashMap,
-    sync::mpsc::{self, Receiver, Sender},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 pub type Card = String;
 pub type NumResults = u32;
 
+/// Every format Commander Spellbook tracks legality for.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Format {
-    Brawl,
     Commander,
+    PauperCommander,
+    /// Pauper Commander with the uncommon commander counted as part of the main deck.
+    PauperCommanderMain,
+    Oathbreaker,
+    PreDh,
+    Brawl,
+    Vintage,
+    Legacy,
+    Premodern,
+    Modern,
+    Pioneer,
+    Standard,
+    Pauper,
+}
+impl Format {
+    pub fn all() -> Vec<Format> {
+        vec![
+            Format::Commander,
+            Format::PauperCommander,
+            Format::PauperCommanderMain,
+            Format::Oathbreaker,
+            Format::PreDh,
+            Format::Brawl,
+            Format::Vintage,
+            Format::Legacy,
+            Format::Premodern,
+            Format::Modern,
+            Format::Pioneer,
+            Format::Standard,
+            Format::Pauper,
+        ]
+    }
+
+    /// The keyword Commander Spellbook uses for this format's legality in searches.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Format::Commander => "commander",
+            Format::PauperCommander => "pauper_commander",
+            Format::PauperCommanderMain => "pauper_commander_main",
+            Format::Oathbreaker => "oathbreaker",
+            Format::PreDh => "predh",
+            Format::Brawl => "brawl",
+            Format::Vintage => "vintage",
+            Format::Legacy => "legacy",
+            Format::Premodern => "premodern",
+            Format::Modern => "modern",
+            Format::Pioneer => "pioneer",
+            Format::Standard => "standard",
+            Format::Pauper => "pauper",
+        }
+    }
+
+    /// The key for this format in the `legalities` of an API variant.
+    pub fn legality_key(&self) -> &'static str {
+        match self {
+            Format::PauperCommander => "pauperCommander",
+            Format::PauperCommanderMain => "pauperCommanderMain",
+            _ => self.keyword(),
+        }
+    }
+
+    pub fn from_keyword(keyword: &str) -> Option<Format> {
+        Format::all()
+            .into_iter()
+            .find(|format| format.keyword() == keyword)
+    }
+
+    pub fn from_legality_key(key: &str) -> Option<Format> {
+        Format::all()
+            .into_iter()
+            .find(|format| format.legality_key() == key)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Commander => "Commander",
+            Format::PauperCommander => "Pauper Commander",
+            Format::PauperCommanderMain => "Pauper Commander (main deck)",
+            Format::Oathbreaker => "Oathbreaker",
+            Format::PreDh => "Pre-DH",
+            Format::Brawl => "Brawl",
+            Format::Vintage => "Vintage",
+            Format::Legacy => "Legacy",
+            Format::Premodern => "Premodern",
+            Format::Modern => "Modern",
+            Format::Pioneer => "Pioneer",
+            Format::Standard => "Standard",
+            Format::Pauper => "Pauper",
+        }
+    }
+
+    /// Whether decks are led by a commander (or oathbreaker), which also makes them singleton.
+    pub fn uses_commander(&self) -> bool {
+        match self {
+            Format::Commander
+            | Format::PauperCommander
+            | Format::PauperCommanderMain
+            | Format::Oathbreaker
+            | Format::PreDh
+            | Format::Brawl => true,
+            Format::Vintage
+            | Format::Legacy
+            | Format::Premodern
+            | Format::Modern
+            | Format::Pioneer
+            | Format::Standard
+            | Format::Pauper => false,
+        }
+    }
+
+    /// The minimum number of cards in a deck, counting the commander.
+    pub fn deck_size(&self) -> u32 {
+        match self {
+            Format::Commander
+            | Format::PauperCommander
+            | Format::PauperCommanderMain
+            | Format::PreDh => 100,
+            Format::Oathbreaker
+            | Format::Brawl
+            | Format::Vintage
+            | Format::Legacy
+            | Format::Premodern
+            | Format::Modern
+            | Format::Pioneer
+            | Format::Standard
+            | Format::Pauper => 60,
+        }
+    }
 }
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    /// Accepts the search keyword, the display name, or a common abbreviation such as "edh" or "pdh".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized: String = s
+            .trim()
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect();
+        let format = match normalized.as_str() {
+            "edh" => Format::Commander,
+            "pdh" => Format::PauperCommander,
+            _ => Format::all()
+                .into_iter()
+                .find(|format| {
+                    let keyword: String = format.keyword().replace('_', "");
+                    let name: String = format
+                        .name()
+                        .to_lowercase()
+                        .chars()
+                        .filter(|c| c.is_alphanumeric())
+                        .collect();
+                    keyword == normalized || name == normalized
+                })
+                .ok_or_else(|| format!("Unknown format: {s}"))?,
+        };
+        Ok(format)
+    }
+}
+
+/// Which Commander Spellbook interface the crawler reads combos from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// The JSON variants API.
+    Api,
+    /// Scraping the rendered search pages. Kept as a fallback for when the API is unavailable.
+    Html,
+}
+
 enum CrawlerMsg {
     FoundCombo,
-    Finished { result: CrawlerResult },
+    /// A source stopped early. The crawl carries on with the others.
+    Failed {
+        source: String,
+        error: CrawlError,
+    },
+    Finished {
+        result: Result<CrawlerResult, CrawlError>,
+    },
 }
 
 enum CrawlerThreadMsg {
     Stop,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// How many cards a combo may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum CardNumber {
+    #[default]
     None,
     Exact(u32),
     GreaterThan(u32),
     LessThan(u32),
+    AtLeast(u32),
+    AtMost(u32),
+    /// `min..max`, not including `max`.
+    Range(u32, u32),
+    /// `min..=max`
+    RangeInclusive(u32, u32),
+}
+impl CardNumber {
+    /// The constraint as comparisons that must all hold, e.g. `2..=4` is `>= 2` and `<= 4`.
+    pub fn comparisons(&self) -> Vec<(Comparison, u32)> {
+        match *self {
+            CardNumber::None => vec![],
+            CardNumber::Exact(count) => vec![(Comparison::Equal, count)],
+            CardNumber::GreaterThan(count) => vec![(Comparison::GreaterThan, count)],
+            CardNumber::LessThan(count) => vec![(Comparison::LessThan, count)],
+            CardNumber::AtLeast(count) => vec![(Comparison::GreaterOrEqual, count)],
+            CardNumber::AtMost(count) => vec![(Comparison::LessOrEqual, count)],
+            CardNumber::Range(min, max) => vec![
+                (Comparison::GreaterOrEqual, min),
+                (Comparison::LessThan, max),
+            ],
+            CardNumber::RangeInclusive(min, max) => vec![
+                (Comparison::GreaterOrEqual, min),
+                (Comparison::LessOrEqual, max),
+            ],
+        }
+    }
+
+    pub fn matches(&self, count: u32) -> bool {
+        self.comparisons()
+            .iter()
+            .all(|(comparison, value)| comparison.compare(count, *value))
+    }
+}
+
+/// Writes the same text form that `FromStr` reads.
+impl std::fmt::Display for CardNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CardNumber::None => write!(f, "any"),
+            CardNumber::Exact(count) => write!(f, "{count}"),
+            CardNumber::GreaterThan(count) => write!(f, ">{count}"),
+            CardNumber::LessThan(count) => write!(f, "<{count}"),
+            CardNumber::AtLeast(count) => write!(f, ">={count}"),
+            CardNumber::AtMost(count) => write!(f, "<={count}"),
+            CardNumber::Range(min, max) => write!(f, "{min}..{max}"),
+            CardNumber::RangeInclusive(min, max) => write!(f, "{min}..={max}"),
+        }
+    }
+}
+
+impl std::str::FromStr for CardNumber {
+    type Err = String;
+
+    /// Accepts a count such as "3", a comparison such as ">=2" or "<4", or a range such as
+    /// "2..4", "2..=4", "2.." or "..=4". An empty string or "any" allows any number of cards.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let count = |text: &str| -> Result<u32, String> {
+            text.trim()
+                .parse()
+                .map_err(|_| format!("Invalid number of cards: {s}"))
+        };
+
+        if s.is_empty() || s.eq_ignore_ascii_case("any") {
+            return Ok(CardNumber::None);
+        }
+        if let Some((min, max)) = s.split_once("..=") {
+            return match min.trim() {
+                "" => Ok(CardNumber::AtMost(count(max)?)),
+                min => {
+                    let (min, max) = (count(min)?, count(max)?);
+                    if min > max {
+                        return Err(format!("Empty range of cards: {s}"));
+                    }
+                    Ok(CardNumber::RangeInclusive(min, max))
+                }
+            };
+        }
+        if let Some((min, max)) = s.split_once("..") {
+            return match (min.trim(), max.trim()) {
+                ("", "") => Ok(CardNumber::None),
+                ("", max) => Ok(CardNumber::LessThan(count(max)?)),
+                (min, "") => Ok(CardNumber::AtLeast(count(min)?)),
+                (min, max) => {
+                    let (min, max) = (count(min)?, count(max)?);
+                    if min >= max {
+                        return Err(format!("Empty range of cards: {s}"));
+                    }
+                    Ok(CardNumber::Range(min, max))
+                }
+            };
+        }
+        if let Some(count_text) = s.strip_prefix(">=") {
+            return Ok(CardNumber::AtLeast(count(count_text)?));
+        }
+        if let Some(count_text) = s.strip_prefix("<=") {
+            return Ok(CardNumber::AtMost(count(count_text)?));
+        }
+        if let Some(count_text) = s.strip_prefix('>') {
+            return Ok(CardNumber::GreaterThan(count(count_text)?));
+        }
+        if let Some(count_text) = s.strip_prefix('<') {
+            return Ok(CardNumber::LessThan(count(count_text)?));
+        }
+        Ok(CardNumber::Exact(count(s.trim_start_matches('='))?))
+    }
+}
+
+/// What to search for. Every source is given the same query.
+#[derive(Debug, Clone, Default)]
+pub struct ComboQuery {
+    /// Only combos that fit within this identity. `None` searches every identity.
+    pub colors: Option<ColorIdentity>,
+    /// Only combos using every one of these cards. Like Commander Spellbook's `card:` term,
+    /// part of a name is enough, e.g. "Ghave" matches "Ghave, Guru of Spores".
+    pub required_cards: Vec<Card>,
+    /// Combos using any of these cards are never kept, e.g. house-banned cards.
+    pub excluded_cards: Vec<Card>,
+    pub format: Option<Format>,
+    pub card_number: CardNumber,
+    /// Only combos producing every one of these outcomes are kept. Combos from sources that
+    /// don't report results can't be checked, so they are dropped when this isn't empty.
+    pub outcomes: Vec<Outcome>,
+    /// Whether sources can use cached pages that haven't expired yet.
+    pub cache: CacheMode,
+}
+impl ComboQuery {
+    /// Checks the parts of the query that sources may not apply themselves.
+    pub fn matches(&self, combo: &Combo) -> bool {
+        let uses = |card: &Card| combo.cards.iter().any(|name| card_matches(name, card));
+        // Upstream results don't always agree with the card count they were asked for
+        self.card_number.matches(combo.cards.len() as u32)
+            && self.required_cards.iter().all(uses)
+            && !self.excluded_cards.iter().any(uses)
+            && self
+                .outcomes
+                .iter()
+                .all(|outcome| outcome.produced_by(combo))
+    }
 }
 
 pub struct CrawlerTask {
     pub result: Option<CrawlerResult>,
+    /// Set instead of `result` when the whole search failed.
+    pub error: Option<CrawlError>,
+    /// Sources that stopped early and why. Their combos up to that point are still in the result.
+    pub failures: Vec<(String, CrawlError)>,
     receiver: Receiver<CrawlerMsg>,
     thread_sender: Sender<CrawlerThreadMsg>,
     handle: Option<std::thread::JoinHandle<()>>,
     combos_found: usize,
 }
 impl CrawlerTask {
-    pub fn new(
-        colors: Vec<Color>,
-        card_name: Option<String>,
-        format: Option<Format>,
-        card_number: CardNumber,
-    ) -> Self {
+    /// Starts searching `sources` in the background. Pages are cached in `storage`.
+    pub fn new(query: ComboQuery, sources: Vec<Box<dyn ComboSource>>, storage: Storage) -> Self {
         // spawn in background thread
+        let (mut task, job) = Self::prepare(query, sources, storage);
+        task.handle = Some(std::thread::spawn(job));
+        task
+    }
+
+    /// Like `new`, but the search runs on one of `pool`'s threads once one is free.
+    pub fn on_pool(
+        query: ComboQuery,
+        sources: Vec<Box<dyn ComboSource>>,
+        storage: Storage,
+        pool: &CrawlerPool,
+    ) -> Self {
+        let (task, job) = Self::prepare(query, sources, storage);
+        pool.run(job);
+        task
+    }
+
+    /// A task that hasn't started yet, and the job that runs its search.
+    fn prepare(
+        query: ComboQuery,
+        sources: Vec<Box<dyn ComboSource>>,
+        storage: Storage,
+    ) -> (Self, Job) {
         let (sender, receiver) = mpsc::channel();
         let (thread_sender, thread_receiver) = mpsc::channel();
-        let handle = std::thread::spawn(move || {
-            let result = crawl(
-                colors,
-                card_name,
-                format,
-                card_number,
-                sender.clone(),
-                thread_receiver,
-            );
-            sender.send(CrawlerMsg::Finished { result }).unwrap();
+        let job = Box::new(move || {
+            let result = crawl(query, sources, &storage, sender.clone(), thread_receiver);
+            // The task may already have been dropped, which is fine
+            let _ = sender.send(CrawlerMsg::Finished { result });
         });
 
-        Self {
+        let task = Self {
             thread_sender,
-            handle: Some(handle),
+            handle: None,
             receiver,
             result: None,
+            error: None,
+            failures: vec![],
             combos_found: 0,
-        }
+        };
+        (task, job)
+    }
+
+    /// Like `new`, but never touches the network. Only cached pages, saved searches and
+    /// imported combos are searched, and sources needing a page that isn't cached fail.
+    pub fn offline(
+        query: ComboQuery,
+        sources: Vec<Box<dyn ComboSource>>,
+        storage: Storage,
+    ) -> Self {
+        Self::new(
+            ComboQuery {
+                cache: CacheMode::Offline,
+                ..query
+            },
+            sources,
+            storage,
+        )
+    }
+
+    /// Like `new`, but every cached page is checked with the site for changes first,
+    /// even ones that haven't expired yet.
+    pub fn refreshing(
+        query: ComboQuery,
+        sources: Vec<Box<dyn ComboSource>>,
+        storage: Storage,
+    ) -> Self {
+        Self::new(
+            ComboQuery {
+                cache: CacheMode::Refresh,
+                ..query
+            },
+            sources,
+            storage,
+        )
     }
 
     pub fn stop(&self) {
-        self.thread_sender.send(CrawlerThreadMsg::Stop).unwrap();
+        let _ = self.thread_sender.send(CrawlerThreadMsg::Stop);
     }
 
     pub fn update(&mut self) {
-        for msg in self.receiver.try_iter() {
-            match msg {
-                CrawlerMsg::FoundCombo => {
-                    self.combos_found += 1;
-                }
-                CrawlerMsg::Finished { result } => {
-                    self.result = Some(result);
+        loop {
+            match self.receiver.try_recv() {
+                Ok(msg) => self.handle(msg),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.crashed();
+                    break;
                 }
             }
         }
     }
 
+    /// Blocks until the search has finished, instead of polling `update`.
+    pub fn wait(&mut self) {
+        while !self.is_finished() {
+            match self.receiver.recv() {
+                Ok(msg) => self.handle(msg),
+                Err(_) => self.crashed(),
+            }
+        }
+    }
+
+    /// Returns true once either `result` or `error` is set.
+    pub fn is_finished(&self) -> bool {
+        self.result.is_some() || self.error.is_some()
+    }
+
+    fn handle(&mut self, msg: CrawlerMsg) {
+        match msg {
+            CrawlerMsg::FoundCombo => {
+                self.combos_found += 1;
+            }
+            CrawlerMsg::Failed { source, error } => {
+                self.failures.push((source, error));
+            }
+            CrawlerMsg::Finished { result } => match result {
+                Ok(result) => self.result = Some(result),
+                Err(error) => self.error = Some(error),
+            },
+        }
+    }
+
+    /// The crawler thread went away without finishing, which only happens if it panicked.
+    fn crashed(&mut self) {
+        if !self.is_finished() {
+            self.error = Some(CrawlError::Crashed);
+        }
+    }
+
     pub fn combos_found(&self) -> usize {
         self.combos_found
     }
@@ -91,245 +517,654 @@ impl CrawlerTask {
 
 impl Drop for CrawlerTask {
     fn drop(&mut self) {
-        self.handle.take().unwrap().join().unwrap();
+        self.stop();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("The crawler thread panicked");
+            }
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed pool of threads for running many searches at once, e.g. one per related card,
+/// without a thread for each. Searches beyond the pool's size wait for a thread to be free.
+pub struct CrawlerPool {
+    sender: Sender<Job>,
+}
+impl CrawlerPool {
+    pub fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("crawler-{i}"))
+                .spawn(move || loop {
+                    // The lock is only held while waiting for the next search, not while running it
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    // The task sees its search crash, the thread carries on with the next one
+                    if std::panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log::error!("The crawler thread panicked");
+                    }
+                })
+                .unwrap();
+        }
+        Self { sender }
+    }
+
+    fn run(&self, job: Job) {
+        // The threads only exit once the pool is dropped
+        let _ = self.sender.send(job);
     }
 }
 
+/// Returns true if `name` matches a card from a query, ignoring case and surrounding whitespace.
+pub fn card_matches(name: &str, card: &str) -> bool {
+    name.to_lowercase()
+        .contains(card.trim().to_lowercase().as_str())
+}
+
 #[derive(Debug, Clone)]
 pub struct CrawlerResult {
-    pub colors: Vec<Color>,
-    pub card: Option<Card>,
-    pub format: Option<Format>,
     pub cards: Vec<(Card, NumResults)>,
-    pub combos: Vec<Vec<Card>>,
+    pub combos: Vec<Combo>,
+    /// Sources that stopped before finishing, e.g. because an offline search found a page
+    /// that isn't cached. The combos are only complete when this is empty.
+    pub failed_sources: Vec<String>,
 }
 
-fn crawl(
-    colors: Vec<Color>,
-    card: Option<String>,
-    format: Option<Format>,
-    card_number: CardNumber,
-    sender: Sender<CrawlerMsg>,
-    receiver: Receiver<CrawlerThreadMsg>,
-) -> CrawlerResult {
-    let mut combos = vec![];
-    let mut card_counts = HashMap::new();
-
-    // Commander spellbook
-    let mut search = format!(
-        "{}&page=1",
-        commander_spellbook_search(&colors, card.clone(), format, card_number)
-    );
-    let mut keep_crawling = true;
-    while keep_crawling {
-        let web_page = WebPage::fetch(&search);
-        let document = web_page.document();
-
-        // Parse the HTML from commander spellbook
-        let selector = scraper::Selector::parse("div.py-1").unwrap();
-        for element in document.select(&selector) {
-            let name_selector = scraper::Selector::parse("div.card-name span").unwrap();
-            let mut combo = vec![];
-            for name_element in element.select(&name_selector) {
-                let name = name_element.text().collect::<Vec<_>>().join("");
-
-                combo.push(name.clone());
-                let count = card_counts.entry(name).or_insert(0);
+impl CrawlerResult {
+    /// The result of a search that found `combos`, counting how many combos use each card.
+    pub fn new(combos: Vec<Combo>) -> Self {
+        let mut card_counts = HashMap::new();
+        for combo in combos.iter() {
+            for name in combo.cards.iter() {
+                let count = card_counts.entry(name.clone()).or_insert(0);
                 *count += 1;
             }
-
-            if !combo.is_empty() {
-                sender.send(CrawlerMsg::FoundCombo).unwrap();
-                combos.push(combo);
-            }
         }
-        // fetch next page
-        let next_selector = scraper::Selector::parse("button.forward-button").unwrap();
-        let next = document.select(&next_selector).next();
-        keep_crawling = next.is_some();
 
-        for msg in receiver.try_iter() {
-            match msg {
-                CrawlerThreadMsg::Stop => {
-                    keep_crawling = false;
-                }
-            }
+        let mut cards = vec![];
+        for (name, count) in card_counts {
+            cards.push((name, count));
         }
 
-        if keep_crawling {
-            let mut split = search.split("&page=");
-            let url = split.next();
-            let page = split.next();
+        // Sort by count, then by name
+        cards.sort_unstable_by_key(|a| (a.1, a.0.clone()));
+        cards.reverse(); // ensure highest count is first
 
-            // Parse the page number as an integer
-            let page = page.unwrap().parse::<u32>().unwrap();
-            let next = page + 1;
-            search = format!("{}&page={}", url.unwrap(), next);
+        Self {
+            cards,
+            combos,
+            failed_sources: vec![],
         }
     }
 
-    // // Fetch from EDH Rec
-    // if (false == true) {
-    //     let url = "https://edhrec.com/combos/ghave-guru-of-spores";
-    //     let response = client.get(url).send().unwrap();
-    //     let body = response.text().unwrap();
-    //     let document = scraper::Html::parse_document(&body);
+    pub fn is_complete(&self) -> bool {
+        self.failed_sources.is_empty()
+    }
+}
 
-    //     let selector = scraper::Selector::parse("div.Grid_grid__EAPIs").unwrap();
-    //     for element in document.select(&selector) {
-    //         println!("COMBO EDHREC");
-    //         let name_selector = scraper::Selector::parse("span.Card_name__Mpa7S").unwrap();
-    //         let mut combo = vec![];
-    //         for name_element in element.select(&name_selector) {
-    //             let name = name_element.text().collect::<Vec<_>>().join("");
-    //             println!("  {}", name);
+/// Searches every source at once, merging the combos they report. A source that fails is
+/// reported with `CrawlerMsg::Failed` and the rest carry on. Only fails if every source did.
+fn crawl(
+    query: ComboQuery,
+    sources: Vec<Box<dyn ComboSource>>,
+    storage: &Storage,
+    sender: Sender<CrawlerMsg>,
+    receiver: Receiver<CrawlerThreadMsg>,
+) -> Result<CrawlerResult, CrawlError> {
+    let mut combos: Vec<Combo> = vec![];
+    let mut failures: Vec<CrawlError> = vec![];
+    let mut failed_sources: Vec<String> = vec![];
+    // Sources may list the same cards in a different order, so combos are matched on their sorted cards
+    let mut combo_indexes: HashMap<Vec<Card>, usize> = HashMap::new();
 
-    //             combo.push(name.clone());
-    //             let count = card_counts.entry(name).or_insert(0);
-    //             *count += 1;
-    //         }
+    // The same search finished recently, so its combos are rebuilt without fetching or parsing
+    // anything. Offline searches use it however old it is.
+    let source_names: Vec<&str> = sources.iter().map(|source| source.name()).collect();
+    if query.cache != CacheMode::Refresh {
+        let max_age = match query.cache {
+            CacheMode::Offline => Duration::MAX,
+            _ => web_page::cache_config().search_ttl,
+        };
+        match saved_search::load(storage, &query, &source_names, max_age) {
+            Ok(Some(result)) => {
+                for _ in result.combos.iter() {
+                    let _ = sender.send(CrawlerMsg::FoundCombo);
+                }
+                return Ok(result);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Couldn't read the saved search: {e}"),
+        }
+    }
 
-    //         if !combo.is_empty() && combo.len() < 3 {
-    //             combos.push(combo);
-    //         }
-    //     }
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let (combo_sender, combo_receiver) = mpsc::channel();
+        for source in sources.iter() {
+            let combo_sender = combo_sender.clone();
+            let stop = &stop;
+            let query = &query;
+            scope.spawn(move || {
+                for combo in source.search(query, storage) {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let failed = combo.is_err();
+                    if combo_sender.send((source.name(), combo)).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(combo_sender);
 
-    //     // Fetch next page?
-    // }
+        loop {
+            match combo_receiver.recv_timeout(Duration::from_millis(50)) {
+                // The query is checked here as well, since not every source can apply all of it
+                Ok((source_name, Ok(combo))) if query.matches(&combo) => {
+                    let mut key = combo.cards.clone();
+                    key.sort();
 
-    combos.dedup();
+                    let combo = Combo {
+                        sources: vec![source_name.to_string()],
+                        ..combo
+                    };
+                    if let Some(index) = combo_indexes.get(&key) {
+                        combos[*index].merge(combo);
+                    } else {
+                        let _ = sender.send(CrawlerMsg::FoundCombo);
+                        combo_indexes.insert(key, combos.len());
+                        combos.push(combo);
+                    }
+                }
+                Ok((source_name, Err(error))) => {
+                    log::warn!("{source_name} failed: {error}");
+                    failures.push(error.clone());
+                    failed_sources.push(source_name.to_string());
+                    let _ = sender.send(CrawlerMsg::Failed {
+                        source: source_name.to_string(),
+                        error,
+                    });
+                }
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
 
-    let mut cards = vec![];
-    for (name, count) in card_counts {
-        cards.push((name, count));
+            if stop_requested(&receiver) {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+
+    if !sources.is_empty() && failures.len() == sources.len() {
+        return Err(failures.remove(0));
     }
 
-    // Sort by count, then by name
-    cards.sort_unstable_by_key(|a| (a.1, a.0.clone()));
-    cards.reverse(); // ensure highest count is first
+    let result = CrawlerResult {
+        failed_sources,
+        ..CrawlerResult::new(combos)
+    };
+    // Searches that were cut short are missing combos, so they aren't reused
+    if failures.is_empty() && !stop.load(Ordering::Relaxed) {
+        if let Err(e) = saved_search::save(storage, &query, &source_names, &result) {
+            log::warn!("Couldn't save the search: {e}");
+        }
+    }
+    Ok(result)
+}
 
-    CrawlerResult {
-        format,
-        cards,
-        combos,
-        colors,
-        card,
+/// Returns true if the task asked the crawler to stop.
+fn stop_requested(receiver: &Receiver<CrawlerThreadMsg>) -> bool {
+    let mut stop = false;
+    for msg in receiver.try_iter() {
+        match msg {
+            CrawlerThreadMsg::Stop => {
+                stop = true;
+            }
+        }
     }
+    stop
+}
+
+pub(crate) fn commander_spellbook_search(
+    colors: Option<ColorIdentity>,
+    required_cards: &[Card],
+    excluded_cards: &[Card],
+    format: Option<Format>,
+    card_number: CardNumber,
+    outcomes: &[Outcome],
+) -> String {
+    let query = commander_spellbook_query(
+        colors,
+        required_cards,
+        excluded_cards,
+        format,
+        card_number,
+        outcomes,
+    );
+    format!("{SPELLBOOK_SEARCH_URL}?q={query}")
 }
 
-// TODO: add format, card count, etc.
-fn commander_spellbook_search(
-    colors: &Vec<Color>,
-    card: Option<String>,
+/// Builds the encoded `q` parameter shared by the search pages and the JSON API.
+pub(crate) fn commander_spellbook_query(
+    colors: Option<ColorIdentity>,
+    required_cards: &[Card],
+    excluded_cards: &[Card],
     format: Option<Format>,
     card_number: CardNumber,
+    outcomes: &[Outcome],
 ) -> String {
-    let make_param = |param: String, text: String, use_quotes: bool| -> String {
-        if use_quotes {
-            format!("{param}%3A%22{text}%22")
-        } else {
-            format!("{param}%3A{text}")
+    let mut terms: Vec<Query> = vec![];
+    for card in required_cards {
+        terms.push(Term::Card(card.clone()).into());
+    }
+    for card in excluded_cards {
+        terms.push(Query::Not(Box::new(Term::Card(card.clone()).into())));
+    }
+    if let Some(colors) = colors {
+        terms.push(Term::ColorIdentity(colors).into());
+    }
+    if let Some(format) = format {
+        terms.push(Term::Legal(format).into());
+    }
+    for outcome in outcomes {
+        if let Some(term) = outcome.search_term() {
+            terms.push(Term::Result(term.to_string()).into());
         }
-    };
+    }
+    for (comparison, count) in card_number.comparisons() {
+        terms.push(Term::CardCount(comparison, count).into());
+    }
 
-    let card = if let Some(card) = card {
-        let card = card
-            .trim()
-            .replace(" ", "%20")
-            .replace(",", "%2C")
-            .replace("'", "%27");
+    Query::And(terms).encode()
+}
 
-        Some(card)
-    } else {
-        None
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sources::ComboStream, web_page::WebPage, Color};
+    use std::sync::atomic::AtomicUsize;
+
+    struct FixedSource {
+        name: &'static str,
+        combos: Vec<Vec<&'static str>>,
+    }
+    impl ComboSource for FixedSource {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn search<'a>(&'a self, _query: &ComboQuery, _storage: &Storage) -> ComboStream<'a> {
+            Box::new(
+                self.combos
+                    .iter()
+                    .map(|cards| Combo::new(cards.iter().map(|card| card.to_string()).collect()))
+                    .map(Ok),
+            )
+        }
+    }
 
-    let mut params = vec![];
+    /// Fails straight away, like a site that's down.
+    struct FailingSource;
+    impl ComboSource for FailingSource {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
 
-    if let Some(card) = card {
-        params.push(make_param("card".to_string(), card, true));
+        fn search<'a>(&'a self, _query: &ComboQuery, _storage: &Storage) -> ComboStream<'a> {
+            Box::new(std::iter::once(Err(CrawlError::Status {
+                url: "https://example.com/".to_string(),
+                status: 503,
+            })))
+        }
     }
 
-    if colors.is_empty() == false {
-        let colors_string = colors
+    #[test]
+    fn crawl_merges_sources() {
+        let sources: Vec<Box<dyn ComboSource>> = vec![
+            Box::new(FixedSource {
+                name: "first",
+                combos: vec![
+                    vec!["Basalt Monolith", "Rings of Brighthearth"],
+                    vec!["Basalt Monolith", "Forsaken Monument"],
+                ],
+            }),
+            Box::new(FixedSource {
+                name: "second",
+                combos: vec![vec!["Rings of Brighthearth", "Basalt Monolith"]],
+            }),
+        ];
+        let (sender, receiver) = mpsc::channel();
+        let (_thread_sender, thread_receiver) = mpsc::channel();
+
+        let result = crawl(
+            ComboQuery::default(),
+            sources,
+            &Storage::open_in_memory().unwrap(),
+            sender,
+            thread_receiver,
+        )
+        .unwrap();
+
+        assert_eq!(2, result.combos.len());
+        assert_eq!(2, receiver.try_iter().count());
+        let rings = result
+            .combos
             .iter()
-            .map(|color| match color {
-                Color::White => "w",
-                Color::Blue => "u",
-                Color::Black => "b",
-                Color::Red => "r",
-                Color::Green => "g",
-                Color::Colorless => "c",
-            })
-            .collect::<Vec<_>>()
-            .join("");
+            .find(|combo| combo.cards.contains(&"Rings of Brighthearth".to_string()))
+            .unwrap();
+        let mut sources = rings.sources.clone();
+        sources.sort();
+        assert_eq!(vec!["first", "second"], sources);
+        assert_eq!(("Basalt Monolith".to_string(), 2), result.cards[0]);
+    }
+
+    /// Reports one detailed combo once the other sources have had time to report theirs.
+    struct SlowDetailedSource;
+    impl ComboSource for SlowDetailedSource {
+        fn name(&self) -> &'static str {
+            "detailed"
+        }
 
-        params.push(make_param("ci".to_string(), colors_string, true));
+        fn search<'a>(&'a self, _query: &ComboQuery, _storage: &Storage) -> ComboStream<'a> {
+            Box::new(std::iter::once_with(|| {
+                std::thread::sleep(Duration::from_millis(200));
+                Ok(Combo {
+                    id: Some("1414-2730".to_string()),
+                    url: Some("https://commanderspellbook.com/combo/1414-2730/".to_string()),
+                    results: vec!["Infinite colorless mana".to_string()],
+                    steps: vec!["Tap Basalt Monolith for three mana.".to_string()],
+                    legal_formats: vec![Format::Commander],
+                    ..Combo::new(vec![
+                        "Basalt Monolith".to_string(),
+                        "Rings of Brighthearth".to_string(),
+                    ])
+                })
+            }))
+        }
     }
 
-    if let Some(format) = format {
-        let format_string = match format {
-            Format::Brawl => "brawl",
-            Format::Commander => "commander",
+    #[test]
+    fn crawl_keeps_details_a_later_source_reports() {
+        let sources: Vec<Box<dyn ComboSource>> = vec![
+            Box::new(SlowDetailedSource),
+            Box::new(FixedSource {
+                name: "thin",
+                combos: vec![vec!["Rings of Brighthearth", "Basalt Monolith"]],
+            }),
+        ];
+        let (sender, _receiver) = mpsc::channel();
+        let (_thread_sender, thread_receiver) = mpsc::channel();
+
+        let result = crawl(
+            ComboQuery::default(),
+            sources,
+            &Storage::open_in_memory().unwrap(),
+            sender,
+            thread_receiver,
+        )
+        .unwrap();
+
+        assert_eq!(1, result.combos.len());
+        let combo = &result.combos[0];
+        assert_eq!(vec!["thin", "detailed"], combo.sources);
+        assert_eq!(Some("1414-2730".to_string()), combo.id);
+        assert!(combo.url.is_some());
+        assert_eq!(vec!["Infinite colorless mana"], combo.results);
+        assert_eq!(1, combo.steps.len());
+        assert_eq!(vec![Format::Commander], combo.legal_formats);
+    }
+
+    #[test]
+    fn crawl_checks_card_count() {
+        let sources: Vec<Box<dyn ComboSource>> = vec![Box::new(FixedSource {
+            name: "first",
+            combos: vec![
+                vec!["Basalt Monolith", "Rings of Brighthearth"],
+                vec!["Basalt Monolith", "Forsaken Monument", "Grim Monolith"],
+                vec![
+                    "Dramatic Reversal",
+                    "Isochron Scepter",
+                    "Sol Ring",
+                    "Arcane Signet",
+                    "Mind Stone",
+                ],
+            ],
+        })];
+        let (sender, _receiver) = mpsc::channel();
+        let (_thread_sender, thread_receiver) = mpsc::channel();
+        let query = ComboQuery {
+            card_number: CardNumber::RangeInclusive(3, 4),
+            ..Default::default()
         };
 
-        params.push(make_param(
-            "legal".to_string(),
-            format_string.to_string(),
-            false,
-        ));
+        let result = crawl(
+            query,
+            sources,
+            &Storage::open_in_memory().unwrap(),
+            sender,
+            thread_receiver,
+        )
+        .unwrap();
+
+        assert_eq!(1, result.combos.len());
+        assert_eq!(3, result.combos[0].cards.len());
     }
 
-    match card_number {
-        CardNumber::None => {}
-        CardNumber::Exact(count) => {
-            params.push(make_param("cards".to_string(), count.to_string(), false));
-        }
-        CardNumber::GreaterThan(count) => {
-            params.push(format!("cards%3E{}", count.to_string()));
+    #[test]
+    fn crawl_checks_required_and_excluded_cards() {
+        let sources: Vec<Box<dyn ComboSource>> = vec![Box::new(FixedSource {
+            name: "first",
+            combos: vec![
+                vec!["Basalt Monolith", "Rings of Brighthearth"],
+                vec!["Basalt Monolith", "Forsaken Monument"],
+                vec!["Basalt Monolith", "Rings of Brighthearth", "Sol Ring"],
+                vec!["Grim Monolith", "Rings of Brighthearth"],
+            ],
+        })];
+        let (sender, _receiver) = mpsc::channel();
+        let (_thread_sender, thread_receiver) = mpsc::channel();
+        let query = ComboQuery {
+            required_cards: vec!["Basalt Monolith".to_string(), "rings".to_string()],
+            excluded_cards: vec!["Sol Ring".to_string()],
+            ..Default::default()
+        };
+
+        let result = crawl(
+            query,
+            sources,
+            &Storage::open_in_memory().unwrap(),
+            sender,
+            thread_receiver,
+        )
+        .unwrap();
+
+        assert_eq!(1, result.combos.len());
+        assert_eq!(
+            vec!["Basalt Monolith", "Rings of Brighthearth"],
+            result.combos[0].cards
+        );
+    }
+
+    #[test]
+    fn crawl_carries_on_when_a_source_fails() {
+        let sources: Vec<Box<dyn ComboSource>> = vec![
+            Box::new(FailingSource),
+            Box::new(FixedSource {
+                name: "first",
+                combos: vec![vec!["Basalt Monolith", "Rings of Brighthearth"]],
+            }),
+        ];
+        let (sender, receiver) = mpsc::channel();
+        let (_thread_sender, thread_receiver) = mpsc::channel();
+
+        let result = crawl(
+            ComboQuery::default(),
+            sources,
+            &Storage::open_in_memory().unwrap(),
+            sender,
+            thread_receiver,
+        )
+        .unwrap();
+
+        assert_eq!(1, result.combos.len());
+        let failures: Vec<(String, CrawlError)> = receiver
+            .try_iter()
+            .filter_map(|msg| match msg {
+                CrawlerMsg::Failed { source, error } => Some((source, error)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(1, failures.len());
+        assert_eq!("failing", failures[0].0);
+        assert!(failures[0].1.is_transient());
+
+        // With nothing left to search the crawl itself fails
+        let mut task = CrawlerTask::new(
+            ComboQuery::default(),
+            vec![Box::new(FailingSource)],
+            Storage::open_in_memory().unwrap(),
+        );
+        task.wait();
+        assert!(task.result.is_none());
+        assert_eq!(1, task.failures.len());
+        assert_eq!(Some(task.failures[0].1.clone()), task.error);
+    }
+
+    #[test]
+    fn commander_spellbook_search_required_and_excluded_cards() {
+        let url = commander_spellbook_search(
+            None,
+            &["Ashnod's Altar".to_string(), "Nim Deathmantle".to_string()],
+            &["Sol Ring".to_string(), "Mana Crypt".to_string()],
+            Some(Format::Commander),
+            CardNumber::None,
+            &[],
+        );
+        assert_eq!(
+            "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20card%3A%22Nim%20Deathmantle%22%20-card%3A%22Sol%20Ring%22%20-card%3A%22Mana%20Crypt%22%20legal%3Acommander",
+            url
+        );
+
+        let (_, q) = url.split_once("?q=").unwrap();
+        assert_eq!(
+            "card:\"Ashnod's Altar\" card:\"Nim Deathmantle\" -card:\"Sol Ring\" -card:\"Mana Crypt\" legal:commander",
+            Query::decode(q).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn card_numbers_parse_and_match() {
+        let cases = [
+            ("any", CardNumber::None),
+            ("3", CardNumber::Exact(3)),
+            (">3", CardNumber::GreaterThan(3)),
+            ("<3", CardNumber::LessThan(3)),
+            (">=2", CardNumber::AtLeast(2)),
+            ("<=4", CardNumber::AtMost(4)),
+            ("2..4", CardNumber::Range(2, 4)),
+            ("2..=4", CardNumber::RangeInclusive(2, 4)),
+        ];
+        for (text, card_number) in cases {
+            assert_eq!(Ok(card_number), text.parse());
+            assert_eq!(text, card_number.to_string());
         }
-        CardNumber::LessThan(count) => {
-            params.push(format!("cards%3C{}", count.to_string()));
+        assert_eq!(Ok(CardNumber::None), "".parse());
+        assert_eq!(Ok(CardNumber::AtLeast(2)), "2..".parse());
+        assert_eq!(Ok(CardNumber::LessThan(4)), "..4".parse());
+        assert_eq!(Ok(CardNumber::AtMost(4)), " ..= 4 ".parse());
+        assert_eq!(Ok(CardNumber::Exact(3)), "=3".parse());
+        for text in ["4..2", "3..3", "4..=3", "two", ">x", "1..2..3"] {
+            assert!(text.parse::<CardNumber>().is_err(), "{text}");
         }
+
+        let range = CardNumber::Range(2, 4);
+        assert!(!range.matches(1));
+        assert!(range.matches(2));
+        assert!(range.matches(3));
+        assert!(!range.matches(4));
+        let inclusive = CardNumber::RangeInclusive(2, 4);
+        assert!(inclusive.matches(4));
+        assert!(!inclusive.matches(5));
+        assert!(CardNumber::AtMost(3).matches(3));
+        assert!(!CardNumber::AtLeast(3).matches(2));
+        assert!(CardNumber::None.matches(7));
     }
 
-    let param_string = params.join("%20");
+    #[test]
+    fn formats_parse_and_serialize() {
+        for format in Format::all() {
+            assert_eq!(Ok(format), format.keyword().parse());
+            assert_eq!(Ok(format), format.name().parse());
+        }
+        assert_eq!(Ok(Format::PauperCommander), "pauper-commander".parse());
+        assert_eq!(Ok(Format::PauperCommander), "PDH".parse());
+        assert_eq!(Ok(Format::PreDh), "Pre-DH".parse());
+        assert!("two-headed giant".parse::<Format>().is_err());
 
-    let output = format!("https://commanderspellbook.com/search/?q={param_string}",);
-    println!("output: {:?}", output);
-    output
-}
+        assert_eq!(
+            "https://commanderspellbook.com/search/?q=legal%3Apauper_commander",
+            commander_spellbook_search(
+                None,
+                &[],
+                &[],
+                Some(Format::PauperCommander),
+                CardNumber::None,
+                &[]
+            )
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn commander_spellbook_search_outcomes() {
+        assert_eq!(
+            "https://commanderspellbook.com/search/?q=ci%3A%22c%22%20result%3A%22win%20the%20game%22",
+            commander_spellbook_search(
+                Some(ColorIdentity::COLORLESS),
+                &[],
+                &[],
+                None,
+                CardNumber::None,
+                &[Outcome::WinTheGame, Outcome::InfiniteMana],
+            )
+        );
+    }
 
     #[test]
     fn commander_spellbook_search_ghave() {
         let cases = vec![
             (
-                vec![],
+                None,
                 Some("Ghave, Guru of Spores".to_string()),
                 None,
                 CardNumber::None,
                 "https://commanderspellbook.com/search/?q=card%3A%22Ghave%2C%20Guru%20of%20Spores%22",
             ),
             (
-                vec![],
+                None,
                 None,
                 Some(Format::Commander),
                 CardNumber::None,
                 "https://commanderspellbook.com/search/?q=legal%3Acommander",
             ),
             (
-                vec![],
+                None,
                 None,
                 Some(Format::Brawl),
                 CardNumber::None,
                 "https://commanderspellbook.com/search/?q=legal%3Abrawl"
             ),
             (
-                vec![],
+                None,
                 Some("Ashnod's Altar".to_string()),
                 None,
                 CardNumber::None,
@@ -337,62 +1172,273 @@ mod tests {
 
             ),
             (
-                vec![Color::White, Color::Green, Color::Black],
+                Some(ColorIdentity::from_colors([
+                    Color::White,
+                    Color::Green,
+                    Color::Black,
+                ])),
                 Some("Ashnod's Altar".to_string()),
                 None,
                 CardNumber::None,
-                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wgb%22",
+                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wbg%22",
 
             ),
             (
-                vec![Color::White, Color::Green, Color::Black],
+                Some(ColorIdentity::from_colors([
+                    Color::White,
+                    Color::Green,
+                    Color::Black,
+                ])),
                 Some("Ashnod's Altar".to_string()),
                 Some(Format::Commander),
                 CardNumber::None,
-                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wgb%22%20legal%3Acommander",
+                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wbg%22%20legal%3Acommander",
             ),
             (
-                vec![Color::White, Color::Green, Color::Black],
+                Some(ColorIdentity::from_colors([
+                    Color::White,
+                    Color::Green,
+                    Color::Black,
+                ])),
                 None,
                 None,
                 CardNumber::None,
-                "https://commanderspellbook.com/search/?q=ci%3A%22wgb%22",
+                "https://commanderspellbook.com/search/?q=ci%3A%22wbg%22",
             ),
-            (vec![], None, None, CardNumber::Exact(3), "https://commanderspellbook.com/search/?q=cards%3A3"),
-            (vec![], Some("Ashnod's Altar".to_string()), None, CardNumber::Exact(3), "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20cards%3A3"),
+            (None, None, None, CardNumber::Exact(3), "https://commanderspellbook.com/search/?q=cards%3A3"),
+            (None, Some("Ashnod's Altar".to_string()), None, CardNumber::Exact(3), "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20cards%3A3"),
             (
-                vec![Color::White, Color::Green, Color::Black],
+                Some(ColorIdentity::from_colors([
+                    Color::White,
+                    Color::Green,
+                    Color::Black,
+                ])),
                 Some("Ashnod's Altar".to_string()),
                 Some(Format::Commander),
                 CardNumber::Exact(3),
-                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wgb%22%20legal%3Acommander%20cards%3A3",
+                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wbg%22%20legal%3Acommander%20cards%3A3",
             ),
             (
-                vec![Color::White, Color::Green, Color::Black],
+                Some(ColorIdentity::from_colors([
+                    Color::White,
+                    Color::Green,
+                    Color::Black,
+                ])),
                 Some("Ashnod's Altar".to_string()),
                 Some(Format::Brawl),
                 CardNumber::Exact(3),
-                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wgb%22%20legal%3Abrawl%20cards%3A3",
+                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20ci%3A%22wbg%22%20legal%3Abrawl%20cards%3A3",
             ),
             (
-                vec![],
+                None,
                 None,
                 None,
                 CardNumber::GreaterThan(3),
                 "https://commanderspellbook.com/search/?q=cards%3E3",
-            ), 
+            ),
             (
-                vec![],
+                None,
                 None,
                 None,
                 CardNumber::LessThan(3),
                 "https://commanderspellbook.com/search/?q=cards%3C3",
-            ), 
+            ),
+            (
+                None,
+                None,
+                None,
+                CardNumber::AtMost(3),
+                "https://commanderspellbook.com/search/?q=cards%3C%3D3",
+            ),
+            (
+                None,
+                Some("Ashnod's Altar".to_string()),
+                None,
+                CardNumber::RangeInclusive(2, 4),
+                "https://commanderspellbook.com/search/?q=card%3A%22Ashnod%27s%20Altar%22%20cards%3E%3D2%20cards%3C%3D4",
+            ),
+            (
+                None,
+                None,
+                None,
+                CardNumber::Range(2, 4),
+                "https://commanderspellbook.com/search/?q=cards%3E%3D2%20cards%3C4",
+            ),
         ];
 
         for (colors, card, format, card_number, expected) in cases {
-            let actual = commander_spellbook_search(&colors, card, format, card_number);
+            let actual =
+                commander_spellbook_search(colors, card.as_slice(), &[], format, card_number, &[]);
             assert_eq!(expected, actual);
+
+            // The query reads back from the url and writes out the same
+            let (_, q) = expected.split_once("?q=").unwrap();
+            let query = Query::decode(q).unwrap();
+            assert_eq!(q, query.encode());
+            assert_eq!(Ok(query.clone()), query.to_string().parse());
+        }
+    }
+
+    /// Counts how many times it's searched, to show when a search was answered from the database.
+    struct CountingSource {
+        searches: Arc<AtomicUsize>,
+    }
+    impl ComboSource for CountingSource {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn search<'a>(&'a self, query: &ComboQuery, storage: &Storage) -> ComboStream<'a> {
+            self.searches.fetch_add(1, Ordering::Relaxed);
+            let source = FixedSource {
+                name: "counting",
+                combos: vec![
+                    vec!["Basalt Monolith", "Rings of Brighthearth"],
+                    vec!["Basalt Monolith", "Forsaken Monument"],
+                ],
+            };
+            Box::new(
+                source
+                    .search(query, storage)
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            )
+        }
+    }
+
+    /// Keeps track of the most searches running at once.
+    struct OverlapSource {
+        running: Arc<AtomicUsize>,
+        most_running: Arc<AtomicUsize>,
+    }
+    impl ComboSource for OverlapSource {
+        fn name(&self) -> &'static str {
+            "overlap"
         }
+
+        fn search<'a>(&'a self, _query: &ComboQuery, _storage: &Storage) -> ComboStream<'a> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_running.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(50));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Box::new(std::iter::empty())
+        }
+    }
+
+    #[test]
+    fn pool_runs_searches_a_few_at_a_time() {
+        let storage = Storage::open_in_memory().unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let pool = CrawlerPool::new(2);
+
+        let mut tasks: Vec<CrawlerTask> = ["Sol Ring", "Mana Crypt", "Mana Vault", "Grim Monolith"]
+            .into_iter()
+            .map(|card| {
+                let sources: Vec<Box<dyn ComboSource>> = vec![Box::new(OverlapSource {
+                    running: running.clone(),
+                    most_running: most_running.clone(),
+                })];
+                let query = ComboQuery {
+                    required_cards: vec![card.to_string()],
+                    ..Default::default()
+                };
+                CrawlerTask::on_pool(query, sources, storage.clone(), &pool)
+            })
+            .collect();
+        for task in tasks.iter_mut() {
+            task.wait();
+            assert!(task.result.is_some());
+        }
+        assert_eq!(2, most_running.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn crawl_reuses_saved_searches() {
+        let storage = Storage::open_in_memory().unwrap();
+        let searches = Arc::new(AtomicUsize::new(0));
+        let search = |query: ComboQuery| {
+            let sources: Vec<Box<dyn ComboSource>> = vec![Box::new(CountingSource {
+                searches: searches.clone(),
+            })];
+            let (sender, _receiver) = mpsc::channel();
+            let (_thread_sender, thread_receiver) = mpsc::channel();
+            crawl(query, sources, &storage, sender, thread_receiver).unwrap()
+        };
+
+        let first = search(ComboQuery::default());
+        let second = search(ComboQuery::default());
+        assert_eq!(1, searches.load(Ordering::Relaxed));
+        assert_eq!(first.cards, second.cards);
+        assert_eq!(
+            vec!["Basalt Monolith", "Rings of Brighthearth"],
+            second.combos[0].cards
+        );
+        assert_eq!(vec!["counting"], second.combos[1].sources);
+
+        // A different query or a refresh searches again
+        search(ComboQuery {
+            required_cards: vec!["Forsaken Monument".to_string()],
+            ..Default::default()
+        });
+        search(ComboQuery {
+            cache: CacheMode::Refresh,
+            ..Default::default()
+        });
+        assert_eq!(3, searches.load(Ordering::Relaxed));
+    }
+
+    /// Fetches one page, like a source scraping a site.
+    struct FetchingSource;
+    impl ComboSource for FetchingSource {
+        fn name(&self) -> &'static str {
+            "fetching"
+        }
+
+        fn search<'a>(&'a self, query: &ComboQuery, storage: &Storage) -> ComboStream<'a> {
+            let page = WebPage::fetch(storage, "https://edhrec.com/combos/sol-ring", query.cache);
+            Box::new(std::iter::once(page.map(|_| {
+                Combo::new(vec!["Sol Ring".to_string(), "Basalt Monolith".to_string()])
+            })))
+        }
+    }
+
+    #[test]
+    fn crawl_offline_reports_partial_results() {
+        let storage = Storage::open_in_memory().unwrap();
+        let searches = Arc::new(AtomicUsize::new(0));
+        let sources = |fetching: bool| {
+            let mut sources: Vec<Box<dyn ComboSource>> = vec![Box::new(CountingSource {
+                searches: searches.clone(),
+            })];
+            if fetching {
+                sources.push(Box::new(FetchingSource));
+            }
+            sources
+        };
+        let search = |query: ComboQuery, sources: Vec<Box<dyn ComboSource>>| {
+            let (sender, _receiver) = mpsc::channel();
+            let (_thread_sender, thread_receiver) = mpsc::channel();
+            crawl(query, sources, &storage, sender, thread_receiver).unwrap()
+        };
+        let offline = ComboQuery {
+            cache: CacheMode::Offline,
+            ..Default::default()
+        };
+
+        // The page isn't cached, so that source fails instead of going online
+        let result = search(offline.clone(), sources(true));
+        assert_eq!(2, result.combos.len());
+        assert_eq!(vec!["fetching"], result.failed_sources);
+        assert!(!result.is_complete());
+
+        // Partial results aren't saved, complete ones are reused offline
+        let result = search(offline.clone(), sources(true));
+        assert!(!result.is_complete());
+        assert_eq!(2, searches.load(Ordering::Relaxed));
+        search(ComboQuery::default(), sources(false));
+        let result = search(offline, sources(false));
+        assert!(result.is_complete());
+        assert_eq!(3, searches.load(Ordering::Relaxed));
     }
 }
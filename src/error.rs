@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+/// Why fetching or searching for combos failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrawlError {
+    /// No response came back, e.g. a timeout or a refused connection.
+    Network {
+        url: String,
+        message: String,
+    },
+    /// The server answered with an error status.
+    Status {
+        url: String,
+        status: u16,
+    },
+    /// The site's robots.txt asks us not to fetch the page.
+    Disallowed {
+        url: String,
+    },
+    /// The search is offline and the page isn't in the cache.
+    NotCached {
+        url: String,
+    },
+    /// A page came back but isn't one worth keeping, e.g. a bot challenge instead of results.
+    Invalid {
+        url: String,
+        reason: String,
+    },
+    /// A page or file didn't contain what we expected.
+    Parse {
+        source: String,
+        message: String,
+    },
+    /// A local file couldn't be read.
+    Io {
+        path: String,
+        message: String,
+    },
+    Database(String),
+    /// The fetch scheduler's workers have stopped.
+    Shutdown,
+    /// The crawler thread stopped without finishing.
+    Crashed,
+}
+impl CrawlError {
+    /// Returns true if trying again later might work.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            CrawlError::Network { .. } => true,
+            // Request Timeout, Too Many Requests and server errors
+            CrawlError::Status { status, .. } => *status == 408 || *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrawlError::Network { url, message } => write!(f, "Unable to reach {url}: {message}"),
+            CrawlError::Status { url, status } => write!(f, "{url} returned HTTP {status}"),
+            CrawlError::Disallowed { url } => write!(f, "{url} is disallowed by robots.txt"),
+            CrawlError::NotCached { url } => {
+                write!(f, "{url} isn't cached and the search is offline")
+            }
+            CrawlError::Invalid { url, reason } => write!(f, "Rejected {url}: {reason}"),
+            CrawlError::Parse { source, message } => {
+                write!(f, "Unable to parse {source}: {message}")
+            }
+            CrawlError::Io { path, message } => write!(f, "Unable to read {path}: {message}"),
+            CrawlError::Database(message) => write!(f, "Database error: {message}"),
+            CrawlError::Shutdown => write!(f, "The fetch scheduler has shut down"),
+            CrawlError::Crashed => write!(f, "The search stopped unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for CrawlError {}
+
+impl From<rusqlite::Error> for CrawlError {
+    fn from(e: rusqlite::Error) -> Self {
+        CrawlError::Database(e.to_string())
+    }
+}
+
+impl From<postgres::Error> for CrawlError {
+    fn from(e: postgres::Error) -> Self {
+        CrawlError::Database(e.to_string())
+    }
+}
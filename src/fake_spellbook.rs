@@ -0,0 +1,400 @@
+use crate::{
+    crawler::card_matches,
+    query::{Query, Term},
+    storage::StoredCombo,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    ops::Deref,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Something to go wrong with a request, to see how the crawler copes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(
+    dead_code,
+    reason = "only tests inject faults, the fake-spellbook command doesn't"
+)]
+pub enum Fault {
+    /// A 429, asking the crawler to wait this long before trying again.
+    TooManyRequests { retry_after: Duration },
+    /// A 500.
+    ServerError,
+    /// The page is served as usual after a delay.
+    Slow(Duration),
+}
+
+/// A stand-in for Commander Spellbook's search pages, serving combos from a fixture instead
+/// of the real site. The `q` and `page` parameters are read the same way, with `ci`, `legal`,
+/// `cards`, `card`, `result` and `popularity` terms applied. Other terms match every combo.
+pub struct FakeSpellbook {
+    combos: Vec<StoredCombo>,
+    page_size: usize,
+    /// Faults waiting for each page number, used up one request at a time.
+    faults: Mutex<HashMap<u32, VecDeque<Fault>>>,
+    /// The path and query of every request, in the order they came in.
+    requests: Mutex<Vec<String>>,
+}
+impl FakeSpellbook {
+    pub fn new(combos: Vec<StoredCombo>) -> Self {
+        Self {
+            combos,
+            page_size: 20,
+            faults: Mutex::new(HashMap::new()),
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    /// Serves the combos from a bulk variants export, such as the ones in `tests/fixtures`.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Ok(Self::new(crate::bulk_data::read(path)?))
+    }
+
+    pub fn with_page_size(self, page_size: usize) -> Self {
+        Self {
+            page_size: page_size.max(1),
+            ..self
+        }
+    }
+
+    /// Makes the next request for `page` fail or stall. Faults for the same page are used in
+    /// the order they were added, so a page can fail twice and then work.
+    #[allow(
+        dead_code,
+        reason = "only tests inject faults, the fake-spellbook command doesn't"
+    )]
+    pub fn inject(&self, page: u32, fault: Fault) {
+        self.faults
+            .lock()
+            .unwrap()
+            .entry(page)
+            .or_default()
+            .push_back(fault);
+    }
+
+    #[allow(dead_code, reason = "for tests to check what the crawler asked for")]
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Starts serving on `address`, e.g. `127.0.0.1:0` for any free port. Every connection
+    /// gets its own thread, so a slow page doesn't hold up the others.
+    pub fn start(self, address: &str) -> Result<FakeServer, String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("{address}: {e}"))?;
+        let url = format!(
+            "http://{}",
+            listener.local_addr().map_err(|e| e.to_string())?
+        );
+        let spellbook = Arc::new(self);
+        let server = spellbook.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let spellbook = server.clone();
+                std::thread::spawn(move || spellbook.handle(stream));
+            }
+        });
+        Ok(FakeServer { url, spellbook })
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        // The headers aren't needed, but the client expects them to be read
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+            header.clear();
+        }
+
+        let target = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .to_string();
+        self.requests.lock().unwrap().push(target.clone());
+        let (status, headers, body) = self.respond(&target);
+        let response = format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let _ = stream.write_all(response.as_bytes());
+    }
+
+    /// The status line, extra headers and body for a request.
+    fn respond(&self, target: &str) -> (&'static str, String, String) {
+        let Ok(url) = reqwest::Url::parse(&format!("http://localhost{target}")) else {
+            return ("400 Bad Request", String::new(), String::new());
+        };
+        if url.path() != "/search/" {
+            return ("404 Not Found", String::new(), String::new());
+        }
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        let page: u32 = param("page")
+            .and_then(|page| page.parse().ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let fault = self
+            .faults
+            .lock()
+            .unwrap()
+            .get_mut(&page)
+            .and_then(|faults| faults.pop_front());
+        match fault {
+            Some(Fault::TooManyRequests { retry_after }) => {
+                let retry_after = format!("Retry-After: {}\r\n", retry_after.as_secs());
+                return ("429 Too Many Requests", retry_after, String::new());
+            }
+            Some(Fault::ServerError) => {
+                return ("500 Internal Server Error", String::new(), String::new());
+            }
+            Some(Fault::Slow(delay)) => std::thread::sleep(delay),
+            None => {}
+        }
+
+        // A search with no terms lists every combo
+        let q = param("q").unwrap_or_default();
+        let query = match q.trim() {
+            "" => Ok(Query::And(vec![])),
+            q => q.parse::<Query>(),
+        };
+        let query = match query {
+            Ok(query) => query,
+            Err(e) => return ("400 Bad Request", String::new(), e),
+        };
+        let html = self.search_page(&query, page);
+        (
+            "200 OK",
+            "Content-Type: text/html; charset=utf-8\r\n".to_string(),
+            html,
+        )
+    }
+
+    /// Renders a page of results with the same markup the crawler scrapes from the real site,
+    /// most popular combos first.
+    fn search_page(&self, query: &Query, page: u32) -> String {
+        let mut found: Vec<&StoredCombo> = self
+            .combos
+            .iter()
+            .filter(|combo| matches(query, combo))
+            .collect();
+        found.sort_by_key(|combo| std::cmp::Reverse(combo.popularity));
+
+        let start = (page as usize - 1) * self.page_size;
+        let mut html = String::from(
+            "<!DOCTYPE html><html><head><title>Search | Commander Spellbook</title></head><body>",
+        );
+        for combo in found.iter().skip(start).take(self.page_size) {
+            let id = escape(combo.combo.id.as_deref().unwrap_or_default());
+            html.push_str(&format!("<div class=\"py-1\"><a href=\"/combo/{id}/\">"));
+            for card in combo.combo.cards.iter() {
+                html.push_str(&format!(
+                    "<div class=\"card-name\"><span>{}</span></div>",
+                    escape(card)
+                ));
+            }
+            html.push_str("</a></div>");
+        }
+        if found.len() > start + self.page_size {
+            html.push_str("<button class=\"forward-button\">Next</button>");
+        }
+        html.push_str("</body></html>");
+        html
+    }
+}
+
+/// A running `FakeSpellbook`. Faults can still be injected through it.
+pub struct FakeServer {
+    /// Where the server is listening, e.g. `http://127.0.0.1:51234`.
+    pub url: String,
+    spellbook: Arc<FakeSpellbook>,
+}
+impl FakeServer {
+    /// The url to give `Spellbook::with_search_url`.
+    pub fn search_url(&self) -> String {
+        format!("{}/search/", self.url)
+    }
+}
+impl Deref for FakeServer {
+    type Target = FakeSpellbook;
+
+    fn deref(&self) -> &Self::Target {
+        &self.spellbook
+    }
+}
+
+/// Whether a combo fits a search, as far as the fake applies the terms.
+fn matches(query: &Query, stored: &StoredCombo) -> bool {
+    let combo = &stored.combo;
+    let contains = |texts: &[String], text: &str| {
+        texts
+            .iter()
+            .any(|t| t.to_lowercase().contains(&text.to_lowercase()))
+    };
+    match query {
+        Query::And(queries) => queries.iter().all(|query| matches(query, stored)),
+        Query::Or(queries) => queries.iter().any(|query| matches(query, stored)),
+        Query::Not(query) => !matches(query, stored),
+        Query::Term(term) => match term {
+            Term::Card(card) => combo.cards.iter().any(|name| card_matches(name, card)),
+            Term::ColorIdentity(colors) => combo
+                .color_identity
+                .is_some_and(|identity| identity.is_subset_of(*colors)),
+            Term::Legal(format) => stored.legal_formats.contains(format),
+            Term::CardCount(comparison, count) => {
+                comparison.compare(combo.cards.len() as u32, *count)
+            }
+            Term::Result(result) => contains(&combo.results, result),
+            Term::Prerequisites(text) => contains(&combo.prerequisites, text),
+            Term::Popularity(comparison, count) => stored
+                .popularity
+                .is_some_and(|popularity| comparison.compare(popularity, *count)),
+            Term::CardType(_) | Term::Price(..) => true,
+        },
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color_identity::ColorIdentity,
+        crawler::{CardNumber, ComboQuery, CrawlerTask, Format},
+        error::CrawlError,
+        fetch::{FetchConfig, FetchScheduler},
+        sources::{ComboSource, Spellbook},
+        storage::Storage,
+    };
+    use std::time::Instant;
+
+    fn start(page_size: usize) -> FakeServer {
+        FakeSpellbook::open(Path::new("tests/fixtures/spellbook_bulk_variants.json"))
+            .unwrap()
+            .with_page_size(page_size)
+            .start("127.0.0.1:0")
+            .unwrap()
+    }
+
+    /// A store fetching over HTTP without a rate limit, retrying quickly.
+    fn storage() -> Storage {
+        Storage::open_in_memory()
+            .unwrap()
+            .with_scheduler(FetchScheduler::new(FetchConfig {
+                requests_per_second: 0.0,
+                retry_delay: Duration::from_millis(10),
+                ..Default::default()
+            }))
+    }
+
+    fn search(server: &FakeServer, query: ComboQuery) -> Result<Vec<Vec<String>>, CrawlError> {
+        Spellbook::with_search_url(&server.search_url())
+            .search(&query, &storage())
+            .map(|combo| combo.map(|combo| combo.cards))
+            .collect()
+    }
+
+    #[test]
+    fn filters_and_pages_search_results() {
+        let server = start(2);
+
+        assert_eq!(4, search(&server, ComboQuery::default()).unwrap().len());
+        assert_eq!(2, server.requests().len());
+        assert_eq!(
+            vec![
+                vec!["Basalt Monolith", "Rings of Brighthearth"],
+                vec!["Rings of Brighthearth", "Grim Monolith"],
+            ],
+            search(
+                &server,
+                ComboQuery {
+                    colors: Some(ColorIdentity::COLORLESS),
+                    format: Some(Format::Commander),
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            vec![vec![
+                "Incubation Druid",
+                "Staff of Domination",
+                "Nyxbloom Ancient"
+            ]],
+            search(
+                &server,
+                ComboQuery {
+                    card_number: CardNumber::GreaterThan(2),
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            vec![vec!["Basalt Monolith", "Forsaken Monument"]],
+            search(
+                &server,
+                ComboQuery {
+                    required_cards: vec!["basalt".to_string()],
+                    excluded_cards: vec!["Rings of Brighthearth".to_string()],
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn retries_server_errors_and_rate_limits() {
+        let server = start(20);
+        server.inject(1, Fault::ServerError);
+        server.inject(
+            1,
+            Fault::TooManyRequests {
+                retry_after: Duration::from_secs(1),
+            },
+        );
+
+        let started = Instant::now();
+        assert_eq!(4, search(&server, ComboQuery::default()).unwrap().len());
+        assert_eq!(3, server.requests().len());
+        // The 429 asked for a second's break before the last try
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn stops_while_a_page_is_slow() {
+        let server = start(2);
+        server.inject(2, Fault::Slow(Duration::from_millis(500)));
+        let sources: Vec<Box<dyn ComboSource>> =
+            vec![Box::new(Spellbook::with_search_url(&server.search_url()))];
+        let mut task = CrawlerTask::new(ComboQuery::default(), sources, storage());
+        while task.combos_found() < 2 {
+            task.update();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        task.stop();
+        task.wait();
+
+        // The slow page still came back, but nothing after it was asked for
+        assert_eq!(2, task.result.take().unwrap().combos.len());
+        assert_eq!(2, server.requests().len());
+    }
+}
@@ -0,0 +1,905 @@
+use crate::{error::CrawlError, rate_limit::RateLimiter};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// A page to fetch. The validators from a cached copy make it a conditional request,
+/// which the server can answer with a 304 instead of sending the page again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Request {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+impl Request {
+    pub fn get(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A page that came back with a successful status, or a 304 Not Modified with no body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: String,
+}
+impl Response {
+    /// A 200 response with no headers, for stand-in fetchers.
+    #[cfg(test)]
+    pub fn ok(body: &str) -> Self {
+        Self {
+            status: 200,
+            content_type: None,
+            etag: None,
+            last_modified: None,
+            body: body.to_string(),
+        }
+    }
+
+    pub fn not_modified(&self) -> bool {
+        self.status == 304
+    }
+}
+
+pub type FetchResult = Result<Response, CrawlError>;
+
+/// How the scheduler's workers send requests: over HTTP, or from a recording in tests.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: &Request) -> FetchResult;
+}
+impl<F> Transport for F
+where
+    F: Fn(&Request) -> FetchResult + Send + Sync,
+{
+    fn send(&self, request: &Request) -> FetchResult {
+        self(request)
+    }
+}
+
+/// How the shared scheduler fetches pages.
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    /// The most pages fetched at once, across every search.
+    pub workers: usize,
+    pub user_agent: String,
+    /// Requests a second allowed to each host. Zero turns off rate limiting.
+    pub requests_per_second: f64,
+    /// Requests that can be made back to back before the rate limit kicks in.
+    pub burst: u32,
+    /// Skips pages the site's robots.txt disallows for our user agent. The checks happen in
+    /// the page cache, which keeps robots.txt like any other page.
+    pub respect_robots: bool,
+    /// Times a request is tried again after a timeout, a server error or a 429.
+    pub max_retries: u32,
+    /// The delay before the first retry. It doubles for every retry after that.
+    pub retry_delay: Duration,
+    /// The longest `Retry-After` waited out. A host asking for longer fails the request
+    /// instead, so it can't hold up every worker.
+    pub max_retry_after: Duration,
+    pub timeout: Duration,
+}
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            user_agent: format!("commander-combo-breaker/{}", env!("CARGO_PKG_VERSION")),
+            requests_per_second: 2.0,
+            burst: 4,
+            respect_robots: false,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(120),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+static SCHEDULER: OnceLock<FetchScheduler> = OnceLock::new();
+
+/// Sets up the scheduler used by every search. Must be called before the first fetch.
+pub fn configure(config: FetchConfig) -> Result<(), String> {
+    install(FetchScheduler::new(config))
+}
+
+/// Like `configure`, for a scheduler with a different transport, e.g. one replaying a recording.
+pub fn install(scheduler: FetchScheduler) -> Result<(), String> {
+    SCHEDULER
+        .set(scheduler)
+        .map_err(|_| "The fetch scheduler has already started".to_string())
+}
+
+/// The scheduler shared by every `CrawlerTask`.
+pub fn scheduler() -> &'static FetchScheduler {
+    SCHEDULER.get_or_init(|| FetchScheduler::new(FetchConfig::default()))
+}
+
+/// A request that's queued or being sent, or whose response the first caller is still holding.
+struct Flight {
+    waiting: Vec<Sender<FetchResult>>,
+    /// The response once it's in, given to identical requests until the hold is dropped.
+    response: Option<FetchResult>,
+    /// Whether the first caller's `ResponseHold` is still around.
+    held: bool,
+}
+
+type InFlight = Arc<Mutex<HashMap<Request, Flight>>>;
+
+/// A fixed pool of workers that fetch pages for every search. The number of workers
+/// is the most requests that are ever in flight at once, however many searches are running.
+/// A request that's already in flight isn't sent again, e.g. when overlapping searches miss
+/// the cache for the same page, the later callers wait for the first one's response. They
+/// keep getting it until the first caller is done with it, e.g. once it has cached the page.
+pub struct FetchScheduler {
+    sender: SyncSender<Request>,
+    workers: usize,
+    in_flight: InFlight,
+    /// The user agent whose robots.txt rules pages are checked against, if they're checked.
+    robots_agent: Option<String>,
+}
+impl FetchScheduler {
+    pub fn new(config: FetchConfig) -> Self {
+        let workers = config.workers;
+        let robots_agent = config.respect_robots.then(|| config.user_agent.clone());
+        Self::with_transport(workers, HttpFetcher::new(config)).checking_robots(robots_agent)
+    }
+
+    /// Has pages checked against the robots.txt rules for `user_agent` before they're fetched,
+    /// or not at all if it's `None`.
+    pub fn checking_robots(mut self, user_agent: Option<String>) -> Self {
+        self.robots_agent = user_agent;
+        self
+    }
+
+    pub fn robots_agent(&self) -> Option<&str> {
+        self.robots_agent.as_deref()
+    }
+
+    /// Creates a scheduler whose workers send requests with `transport`, e.g. a `Replayer`
+    /// or a closure standing in for a site.
+    pub fn with_transport(workers: usize, transport: impl Transport + 'static) -> Self {
+        let workers = workers.max(1);
+        // Submitting blocks once this many requests are waiting, rather than queueing without limit
+        let (sender, receiver) = mpsc::sync_channel::<Request>(workers * 4);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let transport: Arc<dyn Transport> = Arc::new(transport);
+        let in_flight = InFlight::default();
+
+        for i in 0..workers {
+            let receiver = receiver.clone();
+            let transport = transport.clone();
+            let in_flight = in_flight.clone();
+            std::thread::Builder::new()
+                .name(format!("fetch-worker-{i}"))
+                .spawn(move || loop {
+                    // The lock is only held while waiting for the next request, not while fetching
+                    let request = match receiver.lock().unwrap().recv() {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    };
+                    // A transport that panics fails the request, the worker carries on
+                    let response =
+                        std::panic::catch_unwind(AssertUnwindSafe(|| transport.send(&request)))
+                            .unwrap_or_else(|_| {
+                                Err(CrawlError::Network {
+                                    url: request.url.clone(),
+                                    message: "the request panicked".to_string(),
+                                })
+                            });
+                    let mut flights = in_flight.lock().unwrap();
+                    let Some(flight) = flights.get_mut(&request) else {
+                        continue;
+                    };
+                    for reply in flight.waiting.drain(..) {
+                        // A search may have stopped waiting, which is fine
+                        let _ = reply.send(response.clone());
+                    }
+                    if flight.held {
+                        flight.response = Some(response);
+                    } else {
+                        flights.remove(&request);
+                    }
+                })
+                .unwrap();
+        }
+
+        Self {
+            sender,
+            workers,
+            in_flight,
+            robots_agent: None,
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Queues a page to be fetched, returning a handle to wait on. If the same request is
+    /// already queued or being sent, or its response is still held, the handle gets that
+    /// one's response instead.
+    pub fn submit(&self, request: Request) -> PendingFetch {
+        let (reply, receiver) = mpsc::channel();
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if let Some(flight) = in_flight.get_mut(&request) {
+                match flight.response.as_ref() {
+                    Some(response) => {
+                        let _ = reply.send(response.clone());
+                    }
+                    None => flight.waiting.push(reply),
+                }
+                return PendingFetch {
+                    receiver,
+                    hold: None,
+                };
+            }
+            let flight = Flight {
+                waiting: vec![reply],
+                response: None,
+                held: true,
+            };
+            in_flight.insert(request.clone(), flight);
+        }
+
+        let hold = ResponseHold {
+            in_flight: self.in_flight.clone(),
+            request: request.clone(),
+        };
+        // Not holding the lock, the queue may be full until a worker finishes
+        if let Err(mpsc::SendError(request)) = self.sender.send(request) {
+            let flight = self.in_flight.lock().unwrap().remove(&request);
+            for reply in flight.map(|flight| flight.waiting).unwrap_or_default() {
+                let _ = reply.send(Err(CrawlError::Shutdown));
+            }
+        }
+        PendingFetch {
+            receiver,
+            hold: Some(hold),
+        }
+    }
+
+    /// Fetches a page, waiting for a free worker.
+    #[cfg(test)]
+    pub fn fetch(&self, url: &str) -> FetchResult {
+        self.submit(Request::get(url)).wait()
+    }
+
+    /// Fetches every page at once, up to the worker limit, returning the results in the same order.
+    #[cfg(test)]
+    pub fn fetch_all(&self, urls: &[String]) -> Vec<FetchResult> {
+        self.send_all(urls.iter().map(|url| Request::get(url)).collect())
+    }
+
+    /// Like `fetch_all`, for requests that may be conditional.
+    #[cfg(test)]
+    pub fn send_all(&self, requests: Vec<Request>) -> Vec<FetchResult> {
+        self.send_all_holding(requests).0
+    }
+
+    /// Like `send_all`, but identical requests get these responses instead of being sent again
+    /// until the holds are dropped. Keep them until the pages are cached, so that a search
+    /// that misses the cache in the meantime doesn't fetch them a second time.
+    pub fn send_all_holding(
+        &self,
+        requests: Vec<Request>,
+    ) -> (Vec<FetchResult>, Vec<ResponseHold>) {
+        let pending: Vec<PendingFetch> = requests
+            .into_iter()
+            .map(|request| self.submit(request))
+            .collect();
+        let mut responses = vec![];
+        let mut holds = vec![];
+        for fetch in pending {
+            let (response, hold) = fetch.wait_holding();
+            responses.push(response);
+            holds.extend(hold);
+        }
+        (responses, holds)
+    }
+}
+
+/// A page that has been queued on a `FetchScheduler`.
+pub struct PendingFetch {
+    receiver: Receiver<FetchResult>,
+    /// Set for the caller whose request is actually sent.
+    hold: Option<ResponseHold>,
+}
+impl PendingFetch {
+    #[cfg(test)]
+    pub fn wait(self) -> FetchResult {
+        self.wait_holding().0
+    }
+
+    /// Like `wait`, also returning the hold on the response if this request is the one that
+    /// was sent. Identical requests are given the response until it's dropped.
+    pub fn wait_holding(self) -> (FetchResult, Option<ResponseHold>) {
+        let response = self.receiver.recv().unwrap_or(Err(CrawlError::Shutdown));
+        (response, self.hold)
+    }
+}
+
+/// Keeps a response for identical requests while the caller that sent it is still dealing
+/// with it. Dropping it lets the next identical request go out again.
+pub struct ResponseHold {
+    in_flight: InFlight,
+    request: Request,
+}
+impl Drop for ResponseHold {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(flight) = in_flight.get_mut(&self.request) {
+            if flight.response.is_some() {
+                in_flight.remove(&self.request);
+            } else {
+                // Still being sent, the worker removes it when it's done
+                flight.held = false;
+            }
+        }
+    }
+}
+
+/// Fetches pages over HTTP, politely: requests are rate limited per host and `Retry-After`
+/// is honoured. Transient failures are retried with exponential backoff.
+pub struct HttpFetcher {
+    client: reqwest::blocking::Client,
+    config: FetchConfig,
+    limiter: RateLimiter,
+}
+impl HttpFetcher {
+    pub fn new(config: FetchConfig) -> Self {
+        Self {
+            client: reqwest::blocking::Client::builder()
+                .user_agent(config.user_agent.clone())
+                .timeout(config.timeout)
+                .build()
+                .unwrap_or_default(),
+            limiter: RateLimiter::new(config.requests_per_second, config.burst),
+            config,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, url: &str) -> FetchResult {
+        self.send(&Request::get(url))
+    }
+
+    pub fn send(&self, request: &Request) -> FetchResult {
+        let url = request.url.as_str();
+        let parsed = reqwest::Url::parse(url).map_err(|e| CrawlError::Network {
+            url: url.to_string(),
+            message: e.to_string(),
+        })?;
+        let host = parsed.host_str().unwrap_or_default().to_string();
+
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire(&host);
+            let (error, retry_after) = match self.get_once(request) {
+                Ok(body) => return Ok(body),
+                Err(failure) => failure,
+            };
+            if !error.is_transient() || attempt >= self.config.max_retries {
+                return Err(error);
+            }
+            if let Some(wait) = retry_after.filter(|wait| *wait > self.config.max_retry_after) {
+                log::warn!("{error}, not waiting {}s to try again", wait.as_secs());
+                return Err(error);
+            }
+
+            let wait = retry_after.unwrap_or_else(|| backoff(self.config.retry_delay, attempt));
+            log::warn!("{error}, trying again in {:.1}s", wait.as_secs_f32());
+            if retry_after.is_some() {
+                // The host asked us to slow down, so every worker holds off, not just this one
+                self.limiter.pause(&host, wait);
+            } else {
+                std::thread::sleep(wait);
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Makes a single request, returning any `Retry-After` delay along with a failure.
+    fn get_once(&self, request: &Request) -> Result<Response, (CrawlError, Option<Duration>)> {
+        let url = request.url.as_str();
+        let network_error = |e: reqwest::Error| CrawlError::Network {
+            url: url.to_string(),
+            message: e.to_string(),
+        };
+        let mut builder = self.client.get(url);
+        if let Some(etag) = request.etag.as_deref() {
+            builder = builder.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = request.last_modified.as_deref() {
+            builder = builder.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = builder.send().map_err(|e| (network_error(e), None))?;
+
+        let not_modified = response.status() == reqwest::StatusCode::NOT_MODIFIED;
+        if !response.status().is_success() && !not_modified {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, SystemTime::now()));
+            let error = CrawlError::Status {
+                url: url.to_string(),
+                status: response.status().as_u16(),
+            };
+            return Err((error, retry_after));
+        }
+        let status = response.status().as_u16();
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let content_type = header(reqwest::header::CONTENT_TYPE);
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let body = response.text().map_err(|e| (network_error(e), None))?;
+        Ok(Response {
+            status,
+            content_type,
+            etag,
+            last_modified,
+            body,
+        })
+    }
+}
+impl Transport for HttpFetcher {
+    fn send(&self, request: &Request) -> FetchResult {
+        HttpFetcher::send(self, request)
+    }
+}
+
+/// The delay before retry number `attempt`, doubling each time up to a minute. The delay is
+/// jittered between half and all of that, so workers that failed together don't retry together.
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    let delay = base
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(Duration::from_secs(60));
+    let half = delay.as_millis() as u64 / 2;
+    Duration::from_millis(half + fastrand::u64(0..=half))
+}
+
+/// Reads a `Retry-After` header, either a number of seconds or an HTTP date such as
+/// "Wed, 21 Oct 2015 07:28:00 GMT".
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    // IMF-fixdate, the only date format servers are meant to send
+    let parts: Vec<&str> = value.split([' ', ':']).collect();
+    let [_, day, month, year, hour, minute, second, "GMT"] = parts[..] else {
+        return None;
+    };
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|name| *name == month)? as i64
+        + 1;
+    let (year, day): (i64, i64) = (year.parse().ok()?, day.parse().ok()?);
+    let (hour, minute, second): (u64, u64, u64) = (
+        hour.parse().ok()?,
+        minute.parse().ok()?,
+        second.parse().ok()?,
+    );
+
+    // Days since 1970-01-01, from Howard Hinnant's days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+
+    let at = SystemTime::UNIX_EPOCH
+        + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second);
+    // A date in the past means we can go again now
+    Some(at.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+    };
+
+    /// Serves each response in turn, recording the request heads it was sent.
+    pub fn scripted_server(responses: Vec<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = requests.clone();
+        std::thread::spawn(move || {
+            for (stream, response) in listener.incoming().zip(responses) {
+                let mut stream = stream.unwrap();
+                let mut head = String::new();
+                let mut reader = BufReader::new(&stream);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                seen.lock().unwrap().push(head);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[test]
+    fn retries_after_too_many_requests() {
+        let (base_url, requests) = scripted_server(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+        ]);
+        let fetcher = HttpFetcher::new(FetchConfig {
+            user_agent: "test-agent/1.0".to_string(),
+            ..Default::default()
+        });
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            Ok(Response {
+                status: 200,
+                content_type: Some("text/html; charset=utf-8".to_string()),
+                etag: None,
+                last_modified: None,
+                body: "hello".to_string(),
+            }),
+            fetcher.get(&format!("{base_url}/page"))
+        );
+        assert!(started.elapsed() >= Duration::from_secs(1));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(2, requests.len());
+        assert!(requests[1]
+            .to_lowercase()
+            .contains("user-agent: test-agent/1.0"));
+    }
+
+    #[test]
+    fn gives_up_when_told_to_wait_too_long() {
+        let (base_url, requests) = scripted_server(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 86400\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ]);
+        let fetcher = HttpFetcher::new(FetchConfig {
+            max_retry_after: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        let started = std::time::Instant::now();
+        let result = fetcher.get(&format!("{base_url}/page"));
+        assert_eq!(
+            Err(CrawlError::Status {
+                url: format!("{base_url}/page"),
+                status: 429
+            }),
+            result
+        );
+        assert!(result.unwrap_err().is_transient());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(1, requests.lock().unwrap().len());
+
+        // The host isn't paused either
+        assert_eq!(
+            Ok("ok".to_string()),
+            fetcher
+                .get(&format!("{base_url}/other"))
+                .map(|page| page.body)
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn retries_server_errors_with_backoff() {
+        let (base_url, requests) = scripted_server(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let fetcher = HttpFetcher::new(FetchConfig {
+            retry_delay: Duration::from_millis(10),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Ok("ok".to_string()),
+            fetcher
+                .get(&format!("{base_url}/flaky"))
+                .map(|page| page.body)
+        );
+        assert_eq!(3, requests.lock().unwrap().len());
+
+        // Missing pages aren't worth trying again
+        assert_eq!(
+            Err(CrawlError::Status {
+                url: format!("{base_url}/missing"),
+                status: 404
+            }),
+            fetcher.get(&format!("{base_url}/missing"))
+        );
+        assert_eq!(4, requests.lock().unwrap().len());
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let (base_url, requests) = scripted_server(vec![
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            3
+        ]);
+        let fetcher = HttpFetcher::new(FetchConfig {
+            max_retries: 2,
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        });
+
+        let error = fetcher.get(&format!("{base_url}/down")).unwrap_err();
+        assert!(error.is_transient());
+        assert_eq!(3, requests.lock().unwrap().len());
+    }
+
+    #[test]
+    fn sends_conditional_requests() {
+        let (base_url, requests) = scripted_server(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\nContent-Length: 2\r\nConnection: close\r\n\r\nv1",
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+        ]);
+        let fetcher = HttpFetcher::new(FetchConfig::default());
+        let url = format!("{base_url}/page");
+
+        let first = fetcher.get(&url).unwrap();
+        assert_eq!(Some("\"v1\"".to_string()), first.etag);
+        assert_eq!(
+            Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+            first.last_modified
+        );
+        assert!(!first.not_modified());
+
+        let second = fetcher
+            .send(&Request {
+                url,
+                etag: first.etag,
+                last_modified: first.last_modified,
+            })
+            .unwrap();
+        assert!(second.not_modified());
+
+        let requests = requests.lock().unwrap();
+        let head = requests[1].to_lowercase();
+        assert!(head.contains("if-none-match: \"v1\""));
+        assert!(head.contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt"));
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        let base = Duration::from_millis(100);
+        for attempt in 0..4 {
+            let full = base * 2u32.pow(attempt);
+            let delay = backoff(base, attempt);
+            assert!(delay >= full / 2 && delay <= full, "{attempt}: {delay:?}");
+        }
+        assert!(backoff(base, 30) <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1445412480);
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            parse_retry_after("120", now)
+        );
+        // 2015-10-21 07:28:00 is 1445412480
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now)
+        );
+        assert_eq!(
+            Some(Duration::ZERO),
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now)
+        );
+        assert_eq!(None, parse_retry_after("soon", now));
+    }
+
+    #[test]
+    fn limits_requests_in_flight() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most_in_flight = Arc::new(AtomicUsize::new(0));
+        let scheduler = {
+            let in_flight = in_flight.clone();
+            let most_in_flight = most_in_flight.clone();
+            FetchScheduler::with_transport(3, move |request: &Request| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_flight.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(Response::ok(&request.url.to_uppercase()))
+            })
+        };
+
+        let urls: Vec<String> = (0..50).map(|i| format!("page-{i}")).collect();
+        let started = std::time::Instant::now();
+        let bodies = scheduler.fetch_all(&urls);
+
+        assert_eq!(50, bodies.len());
+        assert_eq!(Ok(Response::ok("PAGE-0")), bodies[0]);
+        assert_eq!(Ok(Response::ok("PAGE-49")), bodies[49]);
+        assert_eq!(3, most_in_flight.load(Ordering::SeqCst));
+        // 50 pages of 20ms each, three at a time
+        assert!(started.elapsed() < Duration::from_millis(50 * 20));
+    }
+
+    #[test]
+    fn shares_workers_between_threads() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most_in_flight = Arc::new(AtomicUsize::new(0));
+        let scheduler = {
+            let in_flight = in_flight.clone();
+            let most_in_flight = most_in_flight.clone();
+            FetchScheduler::with_transport(2, move |request: &Request| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_flight.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(5));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(Response::ok(&request.url))
+            })
+        };
+
+        std::thread::scope(|scope| {
+            for task in 0..10 {
+                let scheduler = &scheduler;
+                scope.spawn(move || {
+                    for page in 0..3 {
+                        let url = format!("task-{task}/page-{page}");
+                        assert_eq!(Ok(Response::ok(&url)), scheduler.fetch(&url));
+                    }
+                });
+            }
+        });
+
+        assert!(most_in_flight.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn coalesces_requests_already_in_flight() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let scheduler = {
+            let sent = sent.clone();
+            FetchScheduler::with_transport(4, move |request: &Request| {
+                sent.lock().unwrap().push(request.clone());
+                std::thread::sleep(Duration::from_millis(200));
+                Ok(Response::ok(&request.url))
+            })
+        };
+
+        // Overlapping searches all miss the cache for the same pages at once
+        let urls: Vec<String> = ["page-1", "page-2", "page-3"].map(String::from).to_vec();
+        let barrier = Barrier::new(8);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                let (scheduler, urls, barrier) = (&scheduler, &urls, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    let bodies: Vec<String> = scheduler
+                        .fetch_all(urls)
+                        .into_iter()
+                        .map(|response| response.unwrap().body)
+                        .collect();
+                    assert_eq!(*urls, bodies);
+                });
+            }
+        });
+        let mut sent_urls: Vec<String> = sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request: &Request| request.url.clone())
+            .collect();
+        sent_urls.sort();
+        assert_eq!(urls, sent_urls);
+
+        // A conditional request is a different request, its 304 is no use to the others
+        let conditional = Request {
+            etag: Some("\"v1\"".to_string()),
+            ..Request::get("page-1")
+        };
+        let pending = [
+            scheduler.submit(Request::get("page-1")),
+            scheduler.submit(conditional.clone()),
+            scheduler.submit(Request::get("page-1")),
+        ];
+        for fetch in pending {
+            assert!(fetch.wait().is_ok());
+        }
+        let sent = sent.lock().unwrap();
+        assert_eq!(5, sent.len());
+        assert!(sent.contains(&conditional));
+        // Nothing is left waiting once the responses are in
+        assert!(scheduler.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn holds_responses_until_they_are_dealt_with() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let scheduler = {
+            let sent = sent.clone();
+            FetchScheduler::with_transport(2, move |request: &Request| {
+                sent.fetch_add(1, Ordering::SeqCst);
+                Ok(Response::ok(&request.url))
+            })
+        };
+
+        // The response is in, but the first caller hasn't cached it yet
+        let (response, hold) = scheduler.submit(Request::get("page")).wait_holding();
+        assert_eq!(Ok(Response::ok("page")), response);
+        let hold = hold.unwrap();
+        let (again, other_hold) = scheduler.submit(Request::get("page")).wait_holding();
+        assert_eq!(response, again);
+        assert!(other_hold.is_none());
+        assert_eq!(1, sent.load(Ordering::SeqCst));
+
+        // Once it's let go of, the page is asked for again
+        drop(hold);
+        assert!(scheduler.in_flight.lock().unwrap().is_empty());
+        assert_eq!(response, scheduler.fetch("page"));
+        assert_eq!(2, sent.load(Ordering::SeqCst));
+
+        // A caller that stops waiting doesn't leave its response behind
+        drop(scheduler.submit(Request::get("dropped")));
+        assert_eq!(response, scheduler.fetch("page"));
+        let started = std::time::Instant::now();
+        while !scheduler.in_flight.lock().unwrap().is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(4, sent.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn keeps_working_after_a_transport_panics() {
+        let scheduler = FetchScheduler::with_transport(1, |request: &Request| {
+            if request.url == "broken" {
+                panic!("stand-in transport broke");
+            }
+            Ok(Response::ok(&request.url))
+        });
+
+        // The only worker is still there after every panic
+        for _ in 0..3 {
+            assert!(matches!(
+                scheduler.fetch("broken"),
+                Err(CrawlError::Network { url, .. }) if url == "broken"
+            ));
+            assert_eq!(Ok(Response::ok("page")), scheduler.fetch("page"));
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::error::CrawlError;
+use rusqlite::Connection;
+
+/// A step that upgrades the schema by one version.
+type Migration = fn(&Connection) -> rusqlite::Result<()>;
+
+/// Every migration in order, the schema version being how many have run. Databases made
+/// before migrations existed may already have some of these tables and columns, so the
+/// early steps only create what's missing. Never change a migration once it's released,
+/// add another one instead.
+const MIGRATIONS: &[Migration] = &[
+    // 1: the page cache as it was first created
+    |db| {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS html_page (
+                id   INTEGER PRIMARY KEY,
+                url  TEXT NOT NULL,
+                html_body TEXT NOT NULL
+            );",
+        )
+    },
+    // 2: response details, so that error pages aren't cached
+    |db| {
+        add_columns(
+            db,
+            "html_page",
+            &[("status", "INTEGER"), ("content_type", "TEXT")],
+        )
+    },
+    // 3: fetch times and validators for expiry and revalidation
+    |db| {
+        add_columns(
+            db,
+            "html_page",
+            &[
+                ("fetched_at", "INTEGER"),
+                ("etag", "TEXT"),
+                ("last_modified", "TEXT"),
+            ],
+        )
+    },
+    // 4: combos and cards, filled by importing a bulk export
+    |db| {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS cards (
+                id   INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE
+            );
+            CREATE TABLE IF NOT EXISTS combos (
+                id   TEXT PRIMARY KEY,
+                url  TEXT,
+                identity TEXT NOT NULL,
+                card_count INTEGER NOT NULL,
+                prerequisites TEXT NOT NULL DEFAULT '',
+                steps TEXT NOT NULL DEFAULT '',
+                popularity INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS combo_cards (
+                combo_id TEXT NOT NULL REFERENCES combos(id) ON DELETE CASCADE,
+                card_id  INTEGER NOT NULL REFERENCES cards(id),
+                position INTEGER NOT NULL,
+                zones    TEXT NOT NULL DEFAULT '',
+                must_be_commander INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (combo_id, card_id)
+            );
+            CREATE TABLE IF NOT EXISTS combo_results (
+                combo_id TEXT NOT NULL REFERENCES combos(id) ON DELETE CASCADE,
+                result   TEXT NOT NULL,
+                PRIMARY KEY (combo_id, result)
+            );
+            CREATE TABLE IF NOT EXISTS combo_legalities (
+                combo_id TEXT NOT NULL REFERENCES combos(id) ON DELETE CASCADE,
+                format   TEXT NOT NULL,
+                PRIMARY KEY (combo_id, format)
+            );
+            CREATE INDEX IF NOT EXISTS combo_cards_card_id ON combo_cards(card_id);",
+        )
+    },
+    // 5: pages are looked up by url on every fetch
+    |db| {
+        // Older caches could hold several copies of a page, keep the newest
+        db.execute_batch(
+            "DELETE FROM html_page WHERE id NOT IN (SELECT MAX(id) FROM html_page GROUP BY url);
+            CREATE UNIQUE INDEX html_page_url ON html_page(url);",
+        )
+    },
+    // 6: combos found by the crawler, and which searches found them
+    |db| {
+        db.execute_batch(
+            "CREATE TABLE combo_sources (
+                combo_id TEXT NOT NULL REFERENCES combos(id) ON DELETE CASCADE,
+                source   TEXT NOT NULL,
+                PRIMARY KEY (combo_id, source)
+            );
+            CREATE TABLE searches (
+                id  INTEGER PRIMARY KEY,
+                key TEXT NOT NULL UNIQUE,
+                searched_at INTEGER NOT NULL
+            );
+            CREATE TABLE search_combos (
+                search_id INTEGER NOT NULL REFERENCES searches(id) ON DELETE CASCADE,
+                combo_id  TEXT NOT NULL REFERENCES combos(id) ON DELETE CASCADE,
+                position  INTEGER NOT NULL,
+                PRIMARY KEY (search_id, combo_id)
+            );",
+        )
+    },
+    // 7: how often pages come from the cache, for the hit rate
+    |db| {
+        db.execute_batch(
+            "CREATE TABLE cache_counters (
+                name  TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );",
+        )
+    },
+    // 8: compressed page bodies. Existing pages keep their text until they're next read.
+    |db| {
+        db.execute_batch(
+            "ALTER TABLE html_page ADD COLUMN body_format INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE html_page ADD COLUMN compressed_body BLOB;",
+        )
+    },
+    // 9: the sources that found each combo, kept per search. Searches saved before this
+    // take the sources every search had recorded for their combos.
+    |db| {
+        db.execute_batch(
+            "ALTER TABLE search_combos ADD COLUMN sources TEXT NOT NULL DEFAULT '';
+            UPDATE search_combos SET sources = COALESCE((
+                SELECT group_concat(source, char(10)) FROM (
+                    SELECT source FROM combo_sources s
+                    WHERE s.combo_id = search_combos.combo_id ORDER BY rowid
+                )
+            ), '');
+            DROP TABLE combo_sources;",
+        )
+    },
+    // 10: which combos came from a bulk export, as searches store the combos they find in the
+    // same tables. Combos only a search found have no popularity, which only exports give.
+    |db| {
+        db.execute_batch(
+            "ALTER TABLE combos ADD COLUMN imported INTEGER NOT NULL DEFAULT 0;
+            UPDATE combos SET imported = 1
+                WHERE popularity IS NOT NULL OR id NOT IN (SELECT combo_id FROM search_combos);",
+        )
+    },
+    // 11: identities the sources didn't give are stored empty instead of as colorless.
+    // Combos without an id of their own came from sources that never give one.
+    |db| db.execute_batch("UPDATE combos SET identity = '' WHERE id LIKE 'cards:%';"),
+];
+
+/// The schema version this build expects.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+/// The schema version recorded in the database, zero for a new one.
+pub fn schema_version(db: &Connection) -> rusqlite::Result<u32> {
+    db.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Brings the schema up to date, running each missing migration in its own transaction.
+/// Returns the version the database was at before.
+pub fn migrate(db: &mut Connection) -> Result<u32, CrawlError> {
+    let from = schema_version(db)?;
+    if from > latest_version() {
+        return Err(CrawlError::Database(format!(
+            "The database is at schema version {from}, newer than this build's {}",
+            latest_version()
+        )));
+    }
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        let tx = db.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", version as u32 + 1)?;
+        tx.commit()?;
+    }
+    Ok(from)
+}
+
+/// Adds any of `columns` the table doesn't have yet.
+fn add_columns(db: &Connection, table: &str, columns: &[(&str, &str)]) -> rusqlite::Result<()> {
+    let existing = db
+        .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (column, kind) in columns {
+        if !existing.iter().any(|name| name == column) {
+            db.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"),
+                (),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_a_new_database() {
+        let mut db = Connection::open_in_memory().unwrap();
+        assert_eq!(0, migrate(&mut db).unwrap());
+        assert_eq!(latest_version(), schema_version(&db).unwrap());
+
+        // Running again does nothing
+        assert_eq!(latest_version(), migrate(&mut db).unwrap());
+
+        db.execute(
+            "INSERT INTO html_page (url, html_body, status, fetched_at) VALUES ('https://edhrec.com/', 'page', 200, 1)",
+            (),
+        )
+        .unwrap();
+        db.execute("INSERT INTO cards (name) VALUES ('Sol Ring')", ())
+            .unwrap();
+    }
+
+    #[test]
+    fn upgrades_databases_made_before_migrations() {
+        let mut db = Connection::open_in_memory().unwrap();
+        // The table main() used to create, with a page cached twice
+        db.execute_batch(
+            "CREATE TABLE html_page (id INTEGER PRIMARY KEY, url TEXT NOT NULL, html_body TEXT NOT NULL);
+            INSERT INTO html_page (url, html_body) VALUES ('https://edhrec.com/', 'old');
+            INSERT INTO html_page (url, html_body) VALUES ('https://edhrec.com/', 'new');",
+        )
+        .unwrap();
+
+        assert_eq!(0, migrate(&mut db).unwrap());
+
+        let (body, status): (String, Option<u16>) = db
+            .query_row(
+                "SELECT html_body, status FROM html_page WHERE url = 'https://edhrec.com/'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!("new", body);
+        assert_eq!(None, status);
+    }
+
+    #[test]
+    fn refuses_newer_databases() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+        assert!(migrate(&mut db).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{combo::Combo, Color};
+use std::collections::BTreeMap;
+
+/// A category of combo result, e.g. "Infinite colorless mana" or "Win the game".
+/// Ordered from most to least specific so a result lands in the narrowest category that fits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Outcome {
+    WinTheGame,
+    InfiniteDamage,
+    InfiniteLifeLoss,
+    InfiniteMill,
+    InfiniteColorlessMana,
+    InfiniteColoredMana,
+    InfiniteMana,
+    InfiniteTokens,
+    InfiniteCardDraw,
+    InfiniteLifegain,
+    InfiniteStorm,
+    InfiniteTurns,
+}
+impl Outcome {
+    pub fn all() -> Vec<Outcome> {
+        vec![
+            Outcome::WinTheGame,
+            Outcome::InfiniteDamage,
+            Outcome::InfiniteLifeLoss,
+            Outcome::InfiniteMill,
+            Outcome::InfiniteColorlessMana,
+            Outcome::InfiniteColoredMana,
+            Outcome::InfiniteMana,
+            Outcome::InfiniteTokens,
+            Outcome::InfiniteCardDraw,
+            Outcome::InfiniteLifegain,
+            Outcome::InfiniteStorm,
+            Outcome::InfiniteTurns,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::WinTheGame => "Win the game",
+            Outcome::InfiniteDamage => "Infinite damage",
+            Outcome::InfiniteLifeLoss => "Infinite life loss",
+            Outcome::InfiniteMill => "Infinite mill",
+            Outcome::InfiniteColorlessMana => "Infinite colorless mana",
+            Outcome::InfiniteColoredMana => "Infinite colored mana",
+            Outcome::InfiniteMana => "Infinite mana",
+            Outcome::InfiniteTokens => "Infinite tokens",
+            Outcome::InfiniteCardDraw => "Infinite card draw",
+            Outcome::InfiniteLifegain => "Infinite lifegain",
+            Outcome::InfiniteStorm => "Infinite storm count",
+            Outcome::InfiniteTurns => "Infinite turns",
+        }
+    }
+
+    /// Text that Commander Spellbook's `result:` search term can match on, if it
+    /// can express this outcome without missing any combos.
+    pub fn search_term(&self) -> Option<&'static str> {
+        match self {
+            Outcome::WinTheGame => Some("win the game"),
+            Outcome::InfiniteDamage => Some("damage"),
+            // Results spell it "lifeloss" and "life loss", the outcome is checked locally
+            Outcome::InfiniteLifeLoss => Some("life"),
+            Outcome::InfiniteMill => Some("mill"),
+            Outcome::InfiniteColorlessMana => Some("infinite colorless mana"),
+            // Every colour of mana has its own result, "Infinite green mana" and so on
+            Outcome::InfiniteColoredMana | Outcome::InfiniteMana => None,
+            Outcome::InfiniteTokens => Some("token"),
+            Outcome::InfiniteCardDraw => Some("draw"),
+            Outcome::InfiniteLifegain => Some("life"),
+            Outcome::InfiniteStorm => Some("storm"),
+            Outcome::InfiniteTurns => Some("turns"),
+        }
+    }
+
+    /// Returns true if a single combo result, such as "Infinite colored mana", falls in this category.
+    pub fn matches(&self, result: &str) -> bool {
+        let result = result.to_lowercase();
+        let infinite = result.contains("infinite");
+        match self {
+            Outcome::WinTheGame => result.contains("win the game"),
+            Outcome::InfiniteDamage => infinite && result.contains("damage"),
+            Outcome::InfiniteLifeLoss => {
+                infinite && (result.contains("lifeloss") || result.contains("life loss"))
+            }
+            Outcome::InfiniteMill => infinite && result.contains("mill"),
+            Outcome::InfiniteColorlessMana => infinite && result.contains("colorless mana"),
+            // Only results that say the mana is colored, or name a color. Plain "Infinite mana"
+            // could be colorless, so it's just `InfiniteMana`.
+            Outcome::InfiniteColoredMana => {
+                infinite
+                    && result.ends_with("mana")
+                    && result.split_whitespace().any(|word| {
+                        word == "colored"
+                            || Color::all()
+                                .iter()
+                                .any(|color| color.name().eq_ignore_ascii_case(word))
+                    })
+            }
+            Outcome::InfiniteMana => infinite && result.ends_with("mana"),
+            Outcome::InfiniteTokens => infinite && result.contains("token"),
+            Outcome::InfiniteCardDraw => infinite && result.contains("draw"),
+            Outcome::InfiniteLifegain => {
+                infinite && (result.contains("lifegain") || result.contains("life gain"))
+            }
+            Outcome::InfiniteStorm => infinite && result.contains("storm"),
+            Outcome::InfiniteTurns => infinite && result.contains("turns"),
+        }
+    }
+
+    /// Returns true if any of the combo's results fall in this category.
+    pub fn produced_by(&self, combo: &Combo) -> bool {
+        combo.results.iter().any(|result| self.matches(result))
+    }
+
+    /// The narrowest category a result falls in.
+    pub fn categorize(result: &str) -> Option<Outcome> {
+        Outcome::all()
+            .into_iter()
+            .find(|outcome| outcome.matches(result))
+    }
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = String;
+
+    /// Accepts either the display name or a dashed form, e.g. "Win the game" or "win-the-game".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_lowercase().replace(['-', '_'], " ");
+        Outcome::all()
+            .into_iter()
+            .find(|outcome| outcome.name().to_lowercase() == normalized)
+            .ok_or_else(|| format!("Unknown outcome: {s}"))
+    }
+}
+
+/// Groups combos by the categories of their results. A combo with several results
+/// appears under each of their categories, and results that fit no category are grouped under `None`.
+pub fn group_by_outcome(combos: &[Combo]) -> BTreeMap<Option<Outcome>, Vec<&Combo>> {
+    let mut groups: BTreeMap<Option<Outcome>, Vec<&Combo>> = BTreeMap::new();
+    for combo in combos {
+        let mut categories: Vec<Option<Outcome>> = combo
+            .results
+            .iter()
+            .map(|result| Outcome::categorize(result))
+            .collect();
+        if categories.is_empty() {
+            categories.push(None);
+        }
+        categories.sort();
+        categories.dedup();
+
+        for category in categories {
+            groups.entry(category).or_default().push(combo);
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categorizes_results() {
+        assert_eq!(
+            Some(Outcome::InfiniteColorlessMana),
+            Outcome::categorize("Infinite colorless mana")
+        );
+        assert_eq!(
+            Some(Outcome::InfiniteColoredMana),
+            Outcome::categorize("Infinite green mana")
+        );
+        assert!(Outcome::InfiniteMana.matches("Infinite colorless mana"));
+        assert!(!Outcome::InfiniteColoredMana.matches("Infinite colorless mana"));
+        assert!(Outcome::InfiniteColoredMana.matches("Infinite colored mana"));
+        // Mana that could be colorless doesn't count as colored
+        assert_eq!(
+            Some(Outcome::InfiniteMana),
+            Outcome::categorize("Infinite mana")
+        );
+        assert!(!Outcome::InfiniteColoredMana.matches("Infinite mana"));
+        assert_eq!(
+            Some(Outcome::WinTheGame),
+            Outcome::categorize("Win the game")
+        );
+        assert_eq!(None, Outcome::categorize("Infinite ETB"));
+        assert_eq!(Ok(Outcome::WinTheGame), "win-the-game".parse());
+    }
+
+    #[test]
+    fn search_terms_match_every_result_kept_locally() {
+        let results = [
+            "Infinite lifeloss",
+            "Infinite life loss for each opponent",
+            "Infinite lifegain",
+            "Infinite life gain triggers",
+            "Infinite damage",
+            "Infinite colorless mana",
+            "Win the game",
+        ];
+        for outcome in Outcome::all() {
+            let Some(term) = outcome.search_term() else {
+                continue;
+            };
+            for result in results.iter().filter(|result| outcome.matches(result)) {
+                assert!(
+                    result.to_lowercase().contains(term),
+                    "{term} misses {result}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn groups_combos_by_outcome() {
+        let mut mana = Combo::new(vec!["Basalt Monolith".to_string()]);
+        mana.results = vec![
+            "Infinite colorless mana".to_string(),
+            "Infinite lifegain".to_string(),
+        ];
+        let mut win = Combo::new(vec!["Thassa's Oracle".to_string()]);
+        win.results = vec!["Win the game".to_string()];
+        let unknown = Combo::new(vec!["Mystery Card".to_string()]);
+        let combos = vec![mana, win, unknown];
+
+        let groups = group_by_outcome(&combos);
+        assert_eq!(4, groups.len());
+        assert_eq!(1, groups[&Some(Outcome::InfiniteColorlessMana)].len());
+        assert_eq!(1, groups[&Some(Outcome::InfiniteLifegain)].len());
+        assert_eq!(
+            vec!["Thassa's Oracle"],
+            groups[&Some(Outcome::WinTheGame)][0].cards
+        );
+        assert_eq!(1, groups[&None].len());
+    }
+}
@@ -0,0 +1,463 @@
+use crate::{color_identity::ColorIdentity, crawler::Format};
+use url_escape::percent_encoding::AsciiSet;
+
+/// Characters escaped when a query is put in a `q=` parameter. Commander Spellbook
+/// expects apostrophes and parentheses encoded as well as the usual component set.
+const QUERY_VALUE: &AsciiSet = &url_escape::COMPONENT.add(b'\'').add(b'(').add(b')');
+
+/// How a numeric term compares against its value, e.g. the `>=` in `cards>=3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    /// Written `:`, the site also accepts `=`.
+    Equal,
+    GreaterThan,
+    GreaterOrEqual,
+    LessThan,
+    LessOrEqual,
+}
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Equal => ":",
+            Comparison::GreaterThan => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::LessThan => "<",
+            Comparison::LessOrEqual => "<=",
+        }
+    }
+
+    pub fn compare(&self, actual: u32, value: u32) -> bool {
+        match self {
+            Comparison::Equal => actual == value,
+            Comparison::GreaterThan => actual > value,
+            Comparison::GreaterOrEqual => actual >= value,
+            Comparison::LessThan => actual < value,
+            Comparison::LessOrEqual => actual <= value,
+        }
+    }
+}
+
+/// A single search term, e.g. `card:"Ashnod's Altar"` or `cards<4`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    /// A card name, or part of one. Bare words are parsed as card names, the same as on the site.
+    Card(String),
+    ColorIdentity(ColorIdentity),
+    Legal(Format),
+    /// Text in the type line of one of the cards, e.g. "Legendary Creature".
+    CardType(String),
+    /// Text in one of the combo results, e.g. "win the game".
+    Result(String),
+    /// Text in the combo prerequisites.
+    Prerequisites(String),
+    CardCount(Comparison, u32),
+    Popularity(Comparison, u32),
+    /// Price of all the cards, in cents.
+    Price(Comparison, u32),
+}
+
+/// A search in Commander Spellbook's text syntax. Terms side by side must all
+/// match, `or` matches either side and a leading `-` negates a term or group.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Query {
+    Term(Term),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+impl Query {
+    /// The query percent-encoded for use as the `q` parameter of the search pages and API.
+    pub fn encode(&self) -> String {
+        url_escape::encode(&self.to_string(), QUERY_VALUE).to_string()
+    }
+
+    /// Parses the query from the `q` parameter of a search url.
+    #[cfg(test)]
+    pub fn decode(encoded: &str) -> Result<Self, String> {
+        url_escape::decode(encoded).parse()
+    }
+}
+
+impl From<Term> for Query {
+    fn from(term: Term) -> Self {
+        Query::Term(term)
+    }
+}
+
+fn write_text(f: &mut std::fmt::Formatter<'_>, key: &str, text: &str) -> std::fmt::Result {
+    write!(
+        f,
+        "{key}:\"{}\"",
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+impl std::fmt::Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Term::Card(card) => write_text(f, "card", card.trim()),
+            Term::ColorIdentity(colors) => write_text(f, "ci", &colors.symbols()),
+            Term::Legal(format) => write!(f, "legal:{}", format.keyword()),
+            Term::CardType(card_type) => write_text(f, "type", card_type),
+            Term::Result(result) => write_text(f, "result", result),
+            Term::Prerequisites(text) => write_text(f, "prerequisites", text),
+            Term::CardCount(comparison, count) => write!(f, "cards{}{count}", comparison.symbol()),
+            Term::Popularity(comparison, count) => {
+                write!(f, "popularity{}{count}", comparison.symbol())
+            }
+            Term::Price(comparison, cents) if cents % 100 == 0 => {
+                write!(f, "price{}{}", comparison.symbol(), cents / 100)
+            }
+            Term::Price(comparison, cents) => write!(
+                f,
+                "price{}{}.{:02}",
+                comparison.symbol(),
+                cents / 100,
+                cents % 100
+            ),
+        }
+    }
+}
+
+/// Writes the text syntax, adding parentheses only where they're needed.
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let write_group = |f: &mut std::fmt::Formatter<'_>, query: &Query| -> std::fmt::Result {
+            match query {
+                Query::And(queries) | Query::Or(queries) if queries.len() > 1 => {
+                    write!(f, "({query})")
+                }
+                _ => write!(f, "{query}"),
+            }
+        };
+
+        match self {
+            Query::Term(term) => write!(f, "{term}"),
+            Query::Not(query) => {
+                write!(f, "-")?;
+                write_group(f, query)
+            }
+            Query::And(queries) => {
+                for (i, query) in queries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match query {
+                        Query::Or(_) => write_group(f, query)?,
+                        _ => write!(f, "{query}")?,
+                    }
+                }
+                Ok(())
+            }
+            Query::Or(queries) => {
+                for (i, query) in queries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " or ")?;
+                    }
+                    match query {
+                        Query::And(_) => write_group(f, query)?,
+                        _ => write!(f, "{query}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Query {
+    type Err = String;
+
+    /// Parses the site's text syntax, e.g. `card:"Ashnod's Altar" -(ci:wu or cards>4)`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+        };
+        let query = parser.or()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(query),
+            Some(c) => Err(format!("Unexpected '{c}' at {} in: {s}", parser.pos)),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `keyword` if it's the next whole word, ignoring case.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let end = self.pos + keyword.len();
+        if end > self.chars.len() {
+            return false;
+        }
+        let word: String = self.chars[self.pos..end].iter().collect();
+        let ends_word = self
+            .chars
+            .get(end)
+            .is_none_or(|c| c.is_whitespace() || *c == '(' || *c == ')');
+        if word.eq_ignore_ascii_case(keyword) && ends_word {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut queries = vec![self.and()?];
+        loop {
+            self.skip_whitespace();
+            if !self.keyword("or") {
+                break;
+            }
+            queries.push(self.and()?);
+        }
+        Ok(if queries.len() == 1 {
+            queries.remove(0)
+        } else {
+            Query::Or(queries)
+        })
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut queries = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.keyword("and") {
+                continue;
+            }
+            match self.peek() {
+                None | Some(')') => break,
+                _ if self.keyword_ahead("or") => break,
+                _ => queries.push(self.unary()?),
+            }
+        }
+        match queries.len() {
+            0 => Err(format!("Expected a search term at {}", self.pos)),
+            1 => Ok(queries.remove(0)),
+            _ => Ok(Query::And(queries)),
+        }
+    }
+
+    fn keyword_ahead(&mut self, keyword: &str) -> bool {
+        let start = self.pos;
+        let found = self.keyword(keyword);
+        self.pos = start;
+        found
+    }
+
+    fn unary(&mut self) -> Result<Query, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Query::Not(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.pos += 1;
+                let query = self.or()?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(format!("Expected ')' at {}", self.pos));
+                }
+                self.pos += 1;
+                Ok(query)
+            }
+            _ => self.term().map(Query::Term),
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, String> {
+        let start = self.pos;
+        let key: String = self.take_while(|c| c.is_ascii_alphabetic() || c == '_');
+        let comparison = match (self.peek(), self.chars.get(self.pos + 1)) {
+            _ if key.is_empty() => None,
+            (Some(':'), _) | (Some('='), _) => Some((Comparison::Equal, 1)),
+            (Some('>'), Some('=')) => Some((Comparison::GreaterOrEqual, 2)),
+            (Some('<'), Some('=')) => Some((Comparison::LessOrEqual, 2)),
+            (Some('>'), _) => Some((Comparison::GreaterThan, 1)),
+            (Some('<'), _) => Some((Comparison::LessThan, 1)),
+            _ => None,
+        };
+
+        let Some((comparison, len)) = comparison else {
+            // A bare word or quoted string searches card names
+            self.pos = start;
+            return Ok(Term::Card(self.value()?));
+        };
+        self.pos += len;
+        let value = self.value()?;
+
+        let equal_only = || -> Result<(), String> {
+            match comparison {
+                Comparison::Equal => Ok(()),
+                _ => Err(format!(
+                    "{key} can't be compared with {}",
+                    comparison.symbol()
+                )),
+            }
+        };
+        let number = || -> Result<u32, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Expected a number for {key}, found: {value}"))
+        };
+
+        match key.to_lowercase().as_str() {
+            "card" | "name" => equal_only().map(|_| Term::Card(value)),
+            "ci" | "id" | "identity" | "coloridentity" | "color" | "colors" | "c" => {
+                equal_only()?;
+                Ok(Term::ColorIdentity(value.parse()?))
+            }
+            "legal" | "format" => {
+                equal_only()?;
+                Ok(Term::Legal(value.parse()?))
+            }
+            "type" | "t" | "cardtype" => equal_only().map(|_| Term::CardType(value)),
+            "result" | "results" | "res" => equal_only().map(|_| Term::Result(value)),
+            "prerequisites" | "prerequisite" | "pre" => {
+                equal_only().map(|_| Term::Prerequisites(value))
+            }
+            "cards" => Ok(Term::CardCount(comparison, number()?)),
+            "popularity" | "pop" => Ok(Term::Popularity(comparison, number()?)),
+            "price" | "usd" => Ok(Term::Price(comparison, parse_cents(&value)?)),
+            _ => Err(format!("Unknown search term: {key}")),
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&predicate) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// A quoted string, or everything up to the next space or closing parenthesis.
+    fn value(&mut self) -> Result<String, String> {
+        if self.peek() != Some('"') {
+            let value = self.take_while(|c| !c.is_whitespace() && c != ')' && c != '(');
+            if value.is_empty() {
+                return Err(format!("Expected a value at {}", self.pos));
+            }
+            return Ok(value);
+        }
+
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err("Missing closing quote".to_string()),
+                Some('"') => break,
+                Some('\\') if self.pos + 1 < self.chars.len() => {
+                    value.push(self.chars[self.pos + 1]);
+                    self.pos += 1;
+                }
+                Some(c) => value.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok(value)
+    }
+}
+
+/// Parses a price such as "10", "$4.5" or "0.25" into cents.
+fn parse_cents(value: &str) -> Result<u32, String> {
+    let error = || format!("Expected a price, found: {value}");
+    let (dollars, cents) = value
+        .trim_start_matches('$')
+        .split_once('.')
+        .unwrap_or((value.trim_start_matches('$'), ""));
+    if cents.len() > 2 || !cents.chars().all(|c| c.is_ascii_digit()) {
+        return Err(error());
+    }
+    let dollars: u32 = dollars.parse().map_err(|_| error())?;
+    let cents: u32 = format!("{cents:0<2}").parse().map_err(|_| error())?;
+    Ok(dollars * 100 + cents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_writes_text_syntax() {
+        let cases = [
+            ("card:\"Ashnod's Altar\"", "card:\"Ashnod's Altar\""),
+            ("Ghave", "card:\"Ghave\""),
+            ("ci:wbg legal:commander", "ci:\"wbg\" legal:commander"),
+            ("CI=Abzan cards>=3", "ci:\"wbg\" cards>=3"),
+            (
+                "type:legendary -result:\"win the game\"",
+                "type:\"legendary\" -result:\"win the game\"",
+            ),
+            (
+                "pre:\"all permanents\" popularity>1000 price<=10.5",
+                "prerequisites:\"all permanents\" popularity>1000 price<=10.50",
+            ),
+            (
+                "card:Basalt or card:Rings",
+                "card:\"Basalt\" or card:\"Rings\"",
+            ),
+            (
+                "cards<4 (ci:g OR ci:u) and legal:pdh",
+                "cards<4 (ci:\"g\" or ci:\"u\") legal:pauper_commander",
+            ),
+            ("-(cards>4 card:Thoracle)", "-(cards>4 card:\"Thoracle\")"),
+            ("cards:2 ci:c or price>$5", "(cards:2 ci:\"c\") or price>5"),
+            (
+                "card:\"The \\\"Quoted\\\" One\"",
+                "card:\"The \\\"Quoted\\\" One\"",
+            ),
+        ];
+        for (text, expected) in cases {
+            let query: Query = text.parse().unwrap();
+            assert_eq!(expected, query.to_string(), "{text}");
+            assert_eq!(Ok(query.clone()), query.to_string().parse(), "{text}");
+            assert_eq!(Ok(query.clone()), Query::decode(&query.encode()), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_queries() {
+        for text in [
+            "",
+            "(card:Basalt",
+            "card:\"Basalt",
+            "cards>three",
+            "card>Basalt",
+            "legal:two_headed_giant",
+            "colour:wu",
+            "price<1.234",
+            "card:Basalt or",
+        ] {
+            assert!(text.parse::<Query>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn encodes_for_urls() {
+        let query = Query::And(vec![
+            Term::Card("Ghave, Guru of Spores".to_string()).into(),
+            Query::Not(Box::new(Term::CardCount(Comparison::GreaterThan, 3).into())),
+        ]);
+        assert_eq!(
+            "card%3A%22Ghave%2C%20Guru%20of%20Spores%22%20-cards%3E3",
+            query.encode()
+        );
+    }
+}
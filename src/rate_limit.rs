@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Allows `rate` requests a second on average, with bursts of up to `burst` requests.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+    /// Set when the host asks us to back off, e.g. with `Retry-After`.
+    paused_until: Option<Instant>,
+}
+impl TokenBucket {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+            paused_until: None,
+        }
+    }
+
+    /// Takes a token if one is available, otherwise returns how long until one will be.
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            self.paused_until = None;
+        }
+        // Without a rate only a pause holds requests back
+        if self.rate <= 0.0 {
+            return Ok(());
+        }
+
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+
+    /// Stops handing out tokens until `until`.
+    pub fn pause(&mut self, until: Instant) {
+        if self
+            .paused_until
+            .is_none_or(|paused_until| paused_until < until)
+        {
+            self.paused_until = Some(until);
+        }
+        self.tokens = 0.0;
+    }
+}
+
+/// A token bucket for each host, shared by every fetch worker.
+pub struct RateLimiter {
+    rate: f64,
+    burst: u32,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Blocks until a request to `host` is allowed.
+    pub fn acquire(&self, host: &str) {
+        loop {
+            // Sleep without holding the lock so other hosts aren't held up
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let bucket = buckets
+                    .entry(host.to_string())
+                    .or_insert_with(|| TokenBucket::new(self.rate, self.burst));
+                match bucket.try_take(Instant::now()) {
+                    Ok(()) => return,
+                    Err(wait) => wait,
+                }
+            };
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Holds back every request to `host` for `duration`.
+    pub fn pause(&self, host: &str, duration: Duration) {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .entry(host.to_string())
+            .or_insert_with(|| TokenBucket::new(self.rate, self.burst))
+            .pause(Instant::now() + duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_refills_at_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 2);
+        bucket.last_refill = start;
+
+        assert_eq!(Ok(()), bucket.try_take(start));
+        assert_eq!(Ok(()), bucket.try_take(start));
+        assert_eq!(Err(Duration::from_millis(500)), bucket.try_take(start));
+        assert_eq!(Ok(()), bucket.try_take(start + Duration::from_millis(500)));
+
+        // Never more than the burst, however long it's been
+        let later = start + Duration::from_secs(60);
+        assert_eq!(Ok(()), bucket.try_take(later));
+        assert_eq!(Ok(()), bucket.try_take(later));
+        assert!(bucket.try_take(later).is_err());
+    }
+
+    #[test]
+    fn token_bucket_pauses() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 5);
+        bucket.last_refill = start;
+
+        bucket.pause(start + Duration::from_secs(3));
+        assert_eq!(Err(Duration::from_secs(3)), bucket.try_take(start));
+        assert_eq!(
+            Ok(()),
+            bucket.try_take(start + Duration::from_secs(3) + Duration::from_millis(100))
+        );
+
+        // A host asking us to back off is listened to even with rate limiting off
+        let mut unlimited = TokenBucket::new(0.0, 1);
+        unlimited.pause(start + Duration::from_secs(3));
+        assert_eq!(Err(Duration::from_secs(3)), unlimited.try_take(start));
+        assert_eq!(Ok(()), unlimited.try_take(start + Duration::from_secs(3)));
+        assert_eq!(Ok(()), unlimited.try_take(start + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn limits_each_host_separately() {
+        let limiter = RateLimiter::new(20.0, 1);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("commanderspellbook.com");
+        }
+        limiter.acquire("edhrec.com");
+        // Two waits of 50ms for the first host, none for the second
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+    }
+}
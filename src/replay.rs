@@ -0,0 +1,330 @@
+use crate::{
+    error::CrawlError,
+    fetch::{FetchResult, Request, Transport},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A request and what came back for it, as saved in a recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub request: Request,
+    pub response: FetchResult,
+}
+
+/// Reads a recording written by a `Recorder`.
+pub fn read_recording(path: &Path) -> Result<Vec<Exchange>, CrawlError> {
+    let json = std::fs::read_to_string(path).map_err(|e| CrawlError::Io {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    serde_json::from_str(&json).map_err(|e| CrawlError::Parse {
+        source: path.display().to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Sends requests with another transport and saves every exchange to a JSON file, which a
+/// `Replayer` can serve later. The file is rewritten after each request, so a search that's
+/// cut short still leaves a usable recording.
+pub struct Recorder<T> {
+    inner: T,
+    path: PathBuf,
+    exchanges: Mutex<Vec<Exchange>>,
+}
+impl<T: Transport> Recorder<T> {
+    pub fn new(inner: T, path: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            path: path.into(),
+            exchanges: Mutex::new(vec![]),
+        }
+    }
+}
+impl<T: Transport> Transport for Recorder<T> {
+    fn send(&self, request: &Request) -> FetchResult {
+        let response = self.inner.send(request);
+
+        let mut exchanges = self.exchanges.lock().unwrap();
+        exchanges.push(Exchange {
+            request: request.clone(),
+            response: response.clone(),
+        });
+        // A recording that can't be written shouldn't fail the search it's recording
+        match serde_json::to_string_pretty(&*exchanges) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&self.path, json) {
+                    log::warn!("Unable to write {}: {e}", self.path.display());
+                }
+            }
+            Err(e) => log::warn!("Unable to record {}: {e}", request.url),
+        }
+        response
+    }
+}
+
+/// Answers requests from a recording instead of the network. A request gets the first
+/// exchange for its url it hasn't had yet, preferring one with the same validators, so a
+/// page can be recorded once as a 200 and again as a 304. Once they've all been used the
+/// last one is served again.
+pub struct Replayer {
+    exchanges: Vec<Exchange>,
+    used: Mutex<Vec<bool>>,
+}
+impl Replayer {
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        Self {
+            used: Mutex::new(vec![false; exchanges.len()]),
+            exchanges,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, CrawlError> {
+        Ok(Self::new(read_recording(path)?))
+    }
+}
+impl Transport for Replayer {
+    fn send(&self, request: &Request) -> FetchResult {
+        let mut used = self.used.lock().unwrap();
+        let same_url = |i: &usize| self.exchanges[*i].request.url == request.url;
+        let unused = (0..self.exchanges.len()).filter(|i| same_url(i) && !used[*i]);
+        let index = unused
+            .clone()
+            .find(|i| self.exchanges[*i].request == *request)
+            .or_else(|| unused.clone().next())
+            .or_else(|| (0..self.exchanges.len()).rfind(same_url));
+
+        match index {
+            Some(index) => {
+                used[index] = true;
+                self.exchanges[index].response.clone()
+            }
+            None => Err(CrawlError::Network {
+                url: request.url.clone(),
+                message: "no recorded response".to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color_identity::ColorIdentity,
+        crawler::{Backend, ComboQuery, CrawlerTask},
+        fetch::{FetchScheduler, Response},
+        saved_search,
+        sources::{ComboSource, Spellbook},
+        storage::Storage,
+    };
+    use std::time::Duration;
+
+    const TWO_PAGES: &str = "tests/fixtures/recordings/spellbook_two_pages.json";
+    const FIRST_PAGE: &str = "https://backend.commanderspellbook.com/variants/?q=ci%3A%22c%22";
+    const SECOND_PAGE: &str =
+        "https://backend.commanderspellbook.com/variants/?limit=2&offset=2&q=ci%3Ac";
+
+    fn colorless() -> ComboQuery {
+        ComboQuery {
+            colors: Some(ColorIdentity::COLORLESS),
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the response to `url` in a recording.
+    fn replace(exchanges: &mut [Exchange], url: &str, response: FetchResult) {
+        for exchange in exchanges.iter_mut() {
+            if exchange.request.url == url {
+                exchange.response = response.clone();
+            }
+        }
+    }
+
+    /// Crawls the Spellbook API for colorless combos, with every page served by `transport`.
+    fn crawl(storage: Storage, transport: impl Transport + 'static) -> CrawlerTask {
+        let storage = storage.with_scheduler(FetchScheduler::with_transport(2, transport));
+        let sources: Vec<Box<dyn ComboSource>> = vec![Box::new(Spellbook::new(Backend::Api))];
+        CrawlerTask::new(colorless(), sources, storage)
+    }
+
+    #[test]
+    fn records_and_replays_exchanges() {
+        let path = std::env::temp_dir().join(format!("ccb-{}.json", uuid::Uuid::new_v4()));
+        let recorder = Recorder::new(
+            |request: &Request| match request.etag.as_deref() {
+                Some(_) => Ok(Response {
+                    status: 304,
+                    body: String::new(),
+                    ..Response::ok("")
+                }),
+                None if request.url.ends_with("/down") => Err(CrawlError::Status {
+                    url: request.url.clone(),
+                    status: 503,
+                }),
+                None => Ok(Response::ok(&request.url)),
+            },
+            &path,
+        );
+        let conditional = Request {
+            etag: Some("\"v1\"".to_string()),
+            ..Request::get("https://example.com/a")
+        };
+        let requests = [
+            Request::get("https://example.com/a"),
+            conditional.clone(),
+            Request::get("https://example.com/down"),
+        ];
+        let recorded: Vec<FetchResult> = requests.iter().map(|r| recorder.send(r)).collect();
+
+        let replayer = Replayer::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // The 304 is kept for the conditional request, whichever order they come in
+        assert_eq!(recorded[1], replayer.send(&conditional));
+        assert_eq!(recorded[0], replayer.send(&requests[0]));
+        assert_eq!(recorded[2], replayer.send(&requests[2]));
+        // Used up exchanges repeat the last one, unknown urls fail like the network would
+        assert_eq!(recorded[1], replayer.send(&requests[0]));
+        assert!(matches!(
+            replayer.send(&Request::get("https://example.com/b")),
+            Err(CrawlError::Network { .. })
+        ));
+    }
+
+    #[test]
+    fn crawls_every_page_of_a_recording() {
+        let storage = Storage::open_in_memory().unwrap();
+        let mut task = crawl(
+            storage.clone(),
+            Replayer::open(Path::new(TWO_PAGES)).unwrap(),
+        );
+        task.wait();
+
+        let result = task.result.take().unwrap();
+        assert!(result.is_complete());
+        assert_eq!(
+            vec![
+                vec!["Basalt Monolith", "Rings of Brighthearth"],
+                vec!["Rings of Brighthearth", "Grim Monolith"],
+                vec!["Basalt Monolith", "Forsaken Monument"],
+            ],
+            result
+                .combos
+                .iter()
+                .map(|combo| combo.cards.clone())
+                .collect::<Vec<_>>()
+        );
+        // Both pages were cached on the way
+        assert!(storage.cached_page(FIRST_PAGE).unwrap().is_some());
+        assert!(storage.cached_page(SECOND_PAGE).unwrap().is_some());
+    }
+
+    #[test]
+    fn revalidates_cached_pages_from_a_recording() {
+        let storage = Storage::open_in_memory().unwrap();
+        let mut exchanges = read_recording(Path::new(TWO_PAGES)).unwrap();
+        for exchange in exchanges.iter_mut() {
+            if let Ok(response) = exchange.response.as_mut() {
+                response.etag = Some("\"v1\"".to_string());
+            }
+        }
+        crawl(storage.clone(), Replayer::new(exchanges.clone())).wait();
+
+        // A refresh sends the etags back and only gets 304s, so the cached pages are used
+        let not_modified = exchanges.iter().map(|exchange| Exchange {
+            request: Request {
+                etag: Some("\"v1\"".to_string()),
+                ..exchange.request.clone()
+            },
+            response: Ok(Response {
+                status: 304,
+                body: String::new(),
+                ..Response::ok("")
+            }),
+        });
+        let storage = storage.with_scheduler(FetchScheduler::with_transport(
+            2,
+            Replayer::new(not_modified.collect()),
+        ));
+        let sources: Vec<Box<dyn ComboSource>> = vec![Box::new(Spellbook::new(Backend::Api))];
+        let mut task = CrawlerTask::refreshing(colorless(), sources, storage);
+        task.wait();
+        assert_eq!(3, task.result.take().unwrap().combos.len());
+    }
+
+    #[test]
+    fn stopping_keeps_the_combos_found_so_far() {
+        let storage = Storage::open_in_memory().unwrap();
+        let replayer = Replayer::open(Path::new(TWO_PAGES)).unwrap();
+        let mut task = crawl(storage.clone(), move |request: &Request| {
+            if request.url == SECOND_PAGE {
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            replayer.send(request)
+        });
+        while task.combos_found() < 2 {
+            task.update();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        task.stop();
+        task.wait();
+
+        assert_eq!(2, task.result.take().unwrap().combos.len());
+        // A search that was cut short isn't saved
+        let sources = ["commanderspellbook"];
+        assert!(
+            saved_search::load(&storage, &colorless(), &sources, Duration::MAX)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn reports_pages_that_dont_parse() {
+        let mut exchanges = read_recording(Path::new(TWO_PAGES)).unwrap();
+        replace(
+            &mut exchanges,
+            SECOND_PAGE,
+            Ok(Response::ok(
+                r#"{"count": 3, "results": [{"id": "1414-5250", "#,
+            )),
+        );
+        let storage = Storage::open_in_memory().unwrap();
+        let mut task = crawl(storage.clone(), Replayer::new(exchanges));
+        task.wait();
+
+        // The only source failed, so the search did, after finding the first page's combos
+        assert_eq!(2, task.combos_found());
+        assert!(matches!(task.error, Some(CrawlError::Invalid { .. })));
+        // The broken page isn't cached, so the next search fetches it again
+        assert!(storage.cached_page(SECOND_PAGE).unwrap().is_none());
+    }
+
+    #[test]
+    fn skips_empty_pages_and_variants() {
+        let mut exchanges = read_recording(Path::new(TWO_PAGES)).unwrap();
+        replace(
+            &mut exchanges,
+            SECOND_PAGE,
+            Ok(Response::ok(
+                r#"{"count": 3, "next": null, "results": [{"id": "1414", "uses": []}]}"#,
+            )),
+        );
+        let mut task = crawl(Storage::open_in_memory().unwrap(), Replayer::new(exchanges));
+        task.wait();
+        assert_eq!(2, task.result.take().unwrap().combos.len());
+
+        let empty = vec![Exchange {
+            request: Request::get(FIRST_PAGE),
+            response: Ok(Response::ok(r#"{"count": 0, "next": null, "results": []}"#)),
+        }];
+        let mut task = crawl(Storage::open_in_memory().unwrap(), Replayer::new(empty));
+        task.wait();
+        let result = task.result.take().unwrap();
+        assert!(result.combos.is_empty());
+        assert!(result.is_complete());
+    }
+}
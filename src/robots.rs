@@ -0,0 +1,183 @@
+use crate::web_page::WebPage;
+
+/// The rules from a robots.txt file that apply to one user agent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Robots {
+    /// Path patterns, `true` for `Allow` and `false` for `Disallow`.
+    rules: Vec<(String, bool)>,
+}
+impl Robots {
+    /// Allows everything, used when a site has no robots.txt.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Parses the rules for `user_agent`, falling back to the `*` group if no group names it.
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        // The product token, e.g. "commander-combo-breaker" from "commander-combo-breaker/0.1"
+        let product = user_agent
+            .split(['/', ' '])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let mut named: Option<Vec<(String, bool)>> = None;
+        let mut wildcard: Option<Vec<(String, bool)>> = None;
+
+        let mut group_agents: Vec<String> = vec![];
+        let mut group_rules: Vec<(String, bool)> = vec![];
+        let mut in_rules = false;
+        let mut end_group = |agents: &mut Vec<String>, rules: &mut Vec<(String, bool)>| {
+            for agent in agents.iter() {
+                if agent == "*" {
+                    wildcard.get_or_insert_with(Vec::new).extend(rules.clone());
+                } else if !product.is_empty() && *agent == product {
+                    // Groups name a product token exactly, e.g. "commander" isn't us
+                    named.get_or_insert_with(Vec::new).extend(rules.clone());
+                }
+            }
+            agents.clear();
+            rules.clear();
+        };
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match field.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    // A user-agent line after rules starts a new group
+                    if in_rules {
+                        end_group(&mut group_agents, &mut group_rules);
+                        in_rules = false;
+                    }
+                    group_agents.push(value.to_lowercase());
+                }
+                "allow" => {
+                    in_rules = true;
+                    if !value.is_empty() {
+                        group_rules.push((value.to_string(), true));
+                    }
+                }
+                "disallow" => {
+                    in_rules = true;
+                    // An empty disallow allows everything
+                    if !value.is_empty() {
+                        group_rules.push((value.to_string(), false));
+                    }
+                }
+                _ => {}
+            }
+        }
+        end_group(&mut group_agents, &mut group_rules);
+
+        Self {
+            rules: named.or(wildcard).unwrap_or_default(),
+        }
+    }
+
+    /// Checks a path and query, e.g. "/search/?q=card", against the rules.
+    /// The longest matching pattern wins, and `Allow` wins a tie.
+    pub fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(pattern, _)| pattern_matches(pattern, path))
+            .max_by_key(|(pattern, allow)| (pattern.len(), *allow))
+            .is_none_or(|(_, allow)| *allow)
+    }
+}
+
+/// Checks a fetched robots.txt before it's cached. A site without one answers with a 4xx,
+/// which is cached too, as an empty file, so that it isn't asked for again until it expires.
+pub fn check_robots_page(page: &WebPage) -> Result<(), String> {
+    match page.status {
+        None | Some(200) => Ok(()),
+        // A timeout or rate limit says nothing about whether there's a robots.txt
+        Some(status) if (400..500).contains(&status) && status != 408 && status != 429 => Ok(()),
+        Some(status) => Err(format!("HTTP {status}")),
+    }
+}
+
+/// Matches a robots.txt path pattern, where `*` matches anything and a trailing `$` anchors the end.
+pub fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        if last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+# Comments are ignored
+User-agent: *
+Disallow: /admin/
+Disallow: /*.json$
+Allow: /admin/public
+
+User-agent: commander-combo-breaker
+User-agent: other-bot
+Disallow: /search/
+Allow: /search/?q=card
+
+User-agent: greedy-bot
+Disallow: /
+";
+
+    #[test]
+    fn uses_the_group_for_the_user_agent() {
+        let robots = Robots::parse(ROBOTS, "commander-combo-breaker/0.1.0");
+        assert!(!robots.allows("/search/?q=ci"));
+        assert!(robots.allows("/search/?q=card%3A%22Sol%20Ring%22"));
+        // Only the named group applies, not the `*` one
+        assert!(robots.allows("/admin/"));
+
+        let robots = Robots::parse(ROBOTS, "someone-else/1.0");
+        assert!(!robots.allows("/admin/settings"));
+        assert!(robots.allows("/admin/public/page"));
+        assert!(robots.allows("/search/"));
+        assert!(!robots.allows("/combos.json"));
+        assert!(robots.allows("/combos.json?page=2"));
+
+        assert!(!Robots::parse(ROBOTS, "greedy-bot").allows("/anything"));
+    }
+
+    #[test]
+    fn ignores_groups_named_after_part_of_the_token() {
+        let text = "User-agent: *\nDisallow: /admin/\n\nUser-agent: commander\nDisallow: /\n\nUser-agent: c\nDisallow: /search/\n";
+        let robots = Robots::parse(text, "commander-combo-breaker/0.1.0");
+        assert!(robots.allows("/search/"));
+        assert!(!robots.allows("/admin/"));
+
+        let robots = Robots::parse(text, "Commander/2.0");
+        assert!(!robots.allows("/search/"));
+    }
+
+    #[test]
+    fn allows_everything_without_rules() {
+        assert!(Robots::allow_all().allows("/search/"));
+        assert!(Robots::parse("User-agent: *\nDisallow:\n", "bot").allows("/search/"));
+        assert!(Robots::parse("", "bot").allows("/"));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    combo::Combo,
+    crawler::{commander_spellbook_query, ComboQuery, CrawlerResult},
+    error::CrawlError,
+    storage::Storage,
+};
+use std::time::Duration;
+
+/// Identifies a query and the sources it was sent to, so that searching for the same
+/// thing again finds the saved result.
+pub fn search_key(query: &ComboQuery, sources: &[&str]) -> String {
+    let outcomes: Vec<&str> = query
+        .outcomes
+        .iter()
+        .map(|outcome| outcome.name())
+        .collect();
+    let mut sources = sources.to_vec();
+    sources.sort();
+    format!(
+        "{}|{}|{}",
+        commander_spellbook_query(
+            query.colors,
+            &query.required_cards,
+            &query.excluded_cards,
+            query.format,
+            query.card_number,
+            &query.outcomes,
+        ),
+        outcomes.join(","),
+        sources.join(",")
+    )
+}
+
+/// The id a combo is stored under. Combos that don't have one from their source get one made from their cards.
+pub fn combo_id(combo: &Combo) -> String {
+    match combo.id.as_ref() {
+        Some(id) => id.clone(),
+        None => {
+            let mut cards = combo.cards.clone();
+            cards.sort();
+            format!("cards:{}", cards.join("+"))
+        }
+    }
+}
+
+/// Stores the combos from a finished search along with which search found them.
+pub fn save(
+    storage: &Storage,
+    query: &ComboQuery,
+    sources: &[&str],
+    result: &CrawlerResult,
+) -> Result<(), CrawlError> {
+    let combos: Vec<Combo> = result
+        .combos
+        .iter()
+        .map(|combo| Combo {
+            id: Some(combo_id(combo)),
+            ..combo.clone()
+        })
+        .collect();
+    storage.save_search(&search_key(query, sources), &combos)
+}
+
+/// Rebuilds the result of an earlier search for the same query, if it finished less than `max_age` ago.
+pub fn load(
+    storage: &Storage,
+    query: &ComboQuery,
+    sources: &[&str],
+    max_age: Duration,
+) -> Result<Option<CrawlerResult>, CrawlError> {
+    let combos = storage.load_search(&search_key(query, sources), max_age)?;
+    Ok(combos.map(CrawlerResult::new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawler::{CardNumber, Format};
+
+    const SOURCES: &[&str] = &["commanderspellbook", "edhrec", "local"];
+
+    fn combo(id: Option<&str>, cards: &[&str], sources: &[&str]) -> Combo {
+        Combo {
+            id: id.map(|id| id.to_string()),
+            sources: sources.iter().map(|source| source.to_string()).collect(),
+            ..Combo::new(cards.iter().map(|card| card.to_string()).collect())
+        }
+    }
+
+    #[test]
+    fn rebuilds_saved_searches() {
+        let storage = Storage::open_in_memory().unwrap();
+        let query = ComboQuery {
+            required_cards: vec!["Basalt Monolith".to_string()],
+            format: Some(Format::Commander),
+            card_number: CardNumber::AtMost(3),
+            ..Default::default()
+        };
+        let mut detailed = combo(
+            Some("1414-2730"),
+            &["Basalt Monolith", "Rings of Brighthearth"],
+            &["commanderspellbook"],
+        );
+        detailed.results = vec!["Infinite colorless mana".to_string()];
+        let result = CrawlerResult::new(vec![
+            detailed,
+            combo(
+                None,
+                &["Forsaken Monument", "Basalt Monolith"],
+                &["edhrec", "local"],
+            ),
+        ]);
+        save(&storage, &query, SOURCES, &result).unwrap();
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        let loaded = load(&storage, &query, SOURCES, day).unwrap().unwrap();
+        assert_eq!(result.cards, loaded.cards);
+        assert_eq!(2, loaded.combos.len());
+        assert_eq!(Some("1414-2730".to_string()), loaded.combos[0].id);
+        assert_eq!(vec!["Infinite colorless mana"], loaded.combos[0].results);
+        assert_eq!(
+            Some("cards:Basalt Monolith+Forsaken Monument".to_string()),
+            loaded.combos[1].id
+        );
+        assert_eq!(vec!["edhrec", "local"], loaded.combos[1].sources);
+
+        // Other queries and old searches aren't reused
+        let other = ComboQuery {
+            card_number: CardNumber::AtMost(4),
+            ..query.clone()
+        };
+        assert!(load(&storage, &other, SOURCES, day).unwrap().is_none());
+        assert!(load(&storage, &query, SOURCES, Duration::ZERO)
+            .unwrap()
+            .is_none());
+        assert!(load(&storage, &query, &["edhrec"], day).unwrap().is_none());
+
+        // A copy that only knows its cards doesn't wipe out the stored details
+        let sparse = CrawlerResult::new(vec![combo(
+            Some("1414-2730"),
+            &["Basalt Monolith", "Rings of Brighthearth"],
+            &["edhrec"],
+        )]);
+        save(&storage, &other, SOURCES, &sparse).unwrap();
+        let loaded = load(&storage, &other, SOURCES, day).unwrap().unwrap();
+        assert_eq!(vec!["Infinite colorless mana"], loaded.combos[0].results);
+        assert_eq!(vec!["edhrec"], loaded.combos[0].sources);
+    }
+}
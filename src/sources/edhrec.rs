@@ -0,0 +1,95 @@
+use super::{ComboSource, ComboStream};
+use crate::{combo::Combo, crawler::ComboQuery, storage::Storage, web_page::WebPage};
+
+/// EDHREC's combo pages. These are only organised by card, so queries without a card return nothing.
+pub struct Edhrec;
+
+impl ComboSource for Edhrec {
+    fn name(&self) -> &str {
+        "edhrec"
+    }
+
+    fn search<'a>(&'a self, query: &ComboQuery, storage: &Storage) -> ComboStream<'a> {
+        // EDHREC lists combos by card, the crawler checks any other required cards
+        let Some(card) = query.required_cards.first() else {
+            return Box::new(std::iter::empty());
+        };
+
+        let url = format!("https://edhrec.com/combos/{}", card_slug(card));
+        let cache = query.cache;
+        let storage = storage.clone();
+        Box::new(std::iter::once(url).flat_map(move |url| {
+            let page = match WebPage::fetch(&storage, &url, cache) {
+                Ok(page) => page,
+                Err(e) => return vec![Err(e)],
+            };
+            let mut combos = parse_combos(&page.document());
+            for combo in combos.iter_mut() {
+                combo.url = Some(url.clone());
+            }
+            combos.into_iter().map(Ok).collect()
+        }))
+    }
+}
+
+fn parse_combos(document: &scraper::Html) -> Vec<Combo> {
+    let mut combos = vec![];
+
+    let selector = scraper::Selector::parse("div.Grid_grid__EAPIs").unwrap();
+    for element in document.select(&selector) {
+        let name_selector = scraper::Selector::parse("span.Card_name__Mpa7S").unwrap();
+        let mut cards = vec![];
+        for name_element in element.select(&name_selector) {
+            let name = name_element.text().collect::<Vec<_>>().join("");
+            cards.push(name);
+        }
+
+        if !cards.is_empty() {
+            combos.push(Combo::new(cards));
+        }
+    }
+
+    combos
+}
+
+/// Converts a card name to the slug EDHREC uses in its urls, e.g. "Ghave, Guru of Spores" to "ghave-guru-of-spores".
+fn card_slug(card: &str) -> String {
+    card.trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '-')
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn card_slugs() {
+        assert_eq!("ghave-guru-of-spores", card_slug("Ghave, Guru of Spores"));
+        assert_eq!("ashnods-altar", card_slug("Ashnod's Altar"));
+        assert_eq!("niv-mizzet-parun", card_slug(" Niv-Mizzet, Parun "));
+    }
+
+    #[test]
+    fn parses_combo_grid() {
+        let document = scraper::Html::parse_document(
+            r#"<div class="Grid_grid__EAPIs">
+                <span class="Card_name__Mpa7S">Ghave, Guru of Spores</span>
+                <span class="Card_name__Mpa7S">Ashnod's Altar</span>
+                <span class="Card_name__Mpa7S">Doubling Season</span>
+            </div>"#,
+        );
+
+        let combos = parse_combos(&document);
+        assert_eq!(1, combos.len());
+        assert_eq!(
+            vec!["Ghave, Guru of Spores", "Ashnod's Altar", "Doubling Season"],
+            combos[0].cards
+        );
+    }
+}
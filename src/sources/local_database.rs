@@ -0,0 +1,115 @@
+use super::{ComboSource, ComboStream};
+use crate::{combo::Combo, crawler::ComboQuery, error::CrawlError, storage::Storage};
+
+/// Combos imported from a Commander Spellbook bulk export, answered without touching the network.
+pub struct LocalDatabase;
+impl LocalDatabase {
+    fn query(&self, query: &ComboQuery, storage: &Storage) -> Result<Vec<Combo>, CrawlError> {
+        // Matches Commander Spellbook's `ci:` filter, the combo has to fit within the selected
+        // colors. Combos whose identity isn't known can't be shown to fit.
+        Ok(storage
+            .find_combos(query)?
+            .into_iter()
+            .filter(|combo| {
+                query.colors.is_none_or(|colors| {
+                    combo
+                        .color_identity
+                        .is_some_and(|identity| colors.is_superset_of(identity))
+                })
+            })
+            .collect())
+    }
+}
+
+impl ComboSource for LocalDatabase {
+    fn name(&self) -> &str {
+        "local-db"
+    }
+
+    fn search<'a>(&'a self, query: &ComboQuery, storage: &Storage) -> ComboStream<'a> {
+        match self.query(query, storage) {
+            Ok(combos) => Box::new(combos.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bulk_data,
+        crawler::{CardNumber, Format},
+    };
+
+    #[test]
+    fn answers_queries_from_imported_data() {
+        let storage = Storage::open_in_memory().unwrap();
+        bulk_data::import(
+            std::path::Path::new("tests/fixtures/spellbook_bulk_variants.json"),
+            &storage,
+        )
+        .unwrap();
+        let source = LocalDatabase;
+
+        let search = |query: ComboQuery| -> Vec<Vec<String>> {
+            source
+                .search(&query, &storage)
+                .map(|combo| combo.unwrap().cards)
+                .collect()
+        };
+
+        assert_eq!(4, search(ComboQuery::default()).len());
+        assert_eq!(
+            vec![vec![
+                "Incubation Druid",
+                "Staff of Domination",
+                "Nyxbloom Ancient"
+            ]],
+            search(ComboQuery {
+                card_number: CardNumber::GreaterThan(2),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            2,
+            search(ComboQuery {
+                required_cards: vec!["Basalt Monolith".to_string()],
+                ..Default::default()
+            })
+            .len()
+        );
+        assert_eq!(
+            vec![vec!["Basalt Monolith", "Rings of Brighthearth"]],
+            search(ComboQuery {
+                required_cards: vec!["basalt".to_string(), "Rings".to_string()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            3,
+            search(ComboQuery {
+                excluded_cards: vec!["Forsaken Monument".to_string()],
+                ..Default::default()
+            })
+            .len()
+        );
+        assert_eq!(
+            3,
+            search(ComboQuery {
+                format: Some(Format::Commander),
+                ..Default::default()
+            })
+            .len()
+        );
+        // Colorless combos fit in any identity, the green one doesn't fit in blue
+        assert_eq!(
+            3,
+            search(ComboQuery {
+                colors: Some("u".parse().unwrap()),
+                ..Default::default()
+            })
+            .len()
+        );
+    }
+}
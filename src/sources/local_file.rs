@@ -0,0 +1,131 @@
+use super::{ComboSource, ComboStream};
+use crate::{combo::Combo, crawler::ComboQuery, error::CrawlError, storage::Storage};
+use std::path::PathBuf;
+
+/// A JSON file containing a list of combos, e.g. one exported by a teammate.
+/// Only the parts of a query that `ComboQuery::matches` checks apply to it.
+pub struct LocalFile {
+    path: PathBuf,
+    /// "local:" and the file's path, so that searches of different files are saved apart.
+    name: String,
+}
+impl LocalFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        // The same file named two ways is still the same source
+        let name = format!(
+            "local:{}",
+            std::fs::canonicalize(&path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+        );
+        Self { path, name }
+    }
+
+    fn read(&self) -> Result<Vec<Combo>, CrawlError> {
+        let contents = std::fs::read_to_string(&self.path).map_err(|e| CrawlError::Io {
+            path: self.path.display().to_string(),
+            message: e.to_string(),
+        })?;
+
+        serde_json::from_str(&contents).map_err(|e| CrawlError::Parse {
+            source: self.path.display().to_string(),
+            message: e.to_string(),
+        })
+    }
+}
+
+impl ComboSource for LocalFile {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn search<'a>(&'a self, query: &ComboQuery, _storage: &Storage) -> ComboStream<'a> {
+        let combos = match self.read() {
+            Ok(combos) => combos,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        let query = query.clone();
+        Box::new(
+            combos
+                .into_iter()
+                .filter(move |combo| query.matches(combo))
+                .map(Ok),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crawler::CardNumber, outcome::Outcome};
+
+    #[test]
+    fn filters_by_card_and_count() {
+        let path = std::env::temp_dir().join(format!("ccb-local-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"[
+                { "cards": ["Basalt Monolith", "Rings of Brighthearth"], "results": ["Infinite colorless mana"] },
+                { "cards": ["Basalt Monolith", "Forsaken Monument"] },
+                { "cards": ["Incubation Druid", "Staff of Domination", "Nyxbloom Ancient"] }
+            ]"#,
+        )
+        .unwrap();
+        let source = LocalFile::new(&path);
+        let storage = Storage::open_in_memory().unwrap();
+
+        let query = ComboQuery {
+            required_cards: vec!["Basalt Monolith".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(2, source.search(&query, &storage).count());
+
+        let query = ComboQuery {
+            required_cards: vec!["Basalt Monolith".to_string()],
+            excluded_cards: vec!["forsaken monument".to_string()],
+            ..Default::default()
+        };
+        let combos: Vec<Combo> = source
+            .search(&query, &storage)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(1, combos.len());
+        assert_eq!("Rings of Brighthearth", combos[0].cards[1]);
+
+        let query = ComboQuery {
+            card_number: CardNumber::Exact(3),
+            ..Default::default()
+        };
+        let combos: Vec<Combo> = source
+            .search(&query, &storage)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(1, combos.len());
+        assert_eq!("Incubation Druid", combos[0].cards[0]);
+
+        // Everything the crawler checks applies to the file too
+        let query = ComboQuery {
+            outcomes: vec![Outcome::InfiniteColorlessMana],
+            ..Default::default()
+        };
+        let combos: Vec<Combo> = source
+            .search(&query, &storage)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(1, combos.len());
+        assert_eq!("Rings of Brighthearth", combos[0].cards[1]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn names_each_file_apart() {
+        let dir = std::env::temp_dir();
+        let a = LocalFile::new(dir.join("ccb-a.json"));
+        let b = LocalFile::new(dir.join("ccb-b.json"));
+        assert_ne!(a.name(), b.name());
+        assert!(a.name().starts_with("local:"));
+        assert!(a.name().ends_with("ccb-a.json"));
+    }
+}
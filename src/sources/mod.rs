@@ -0,0 +1,26 @@
+mod edhrec;
+mod local_database;
+mod local_file;
+mod spellbook;
+
+pub use edhrec::Edhrec;
+pub use local_database::LocalDatabase;
+pub use local_file::LocalFile;
+pub use spellbook::{check_search_page, Spellbook, SPELLBOOK_SEARCH_URL};
+
+use crate::{combo::Combo, crawler::ComboQuery, error::CrawlError, storage::Storage};
+
+/// A lazily fetched sequence of combos. Sources should only hit the network
+/// when the next combo is requested, so that dropping the stream stops the crawl.
+/// A source that fails yields the error and then ends.
+pub type ComboStream<'a> = Box<dyn Iterator<Item = Result<Combo, CrawlError>> + 'a>;
+
+/// Somewhere combos can be pulled from.
+pub trait ComboSource: Send + Sync {
+    /// Name recorded on every combo this source reports. Saved searches are keyed by it too,
+    /// so sources that can give different combos need different names.
+    fn name(&self) -> &str;
+
+    /// Sources that fetch pages cache them in `storage`.
+    fn search<'a>(&'a self, query: &ComboQuery, storage: &Storage) -> ComboStream<'a>;
+}
@@ -0,0 +1,330 @@
+use super::{ComboSource, ComboStream};
+use crate::{
+    combo::Combo,
+    crawler::{commander_spellbook_query, commander_spellbook_search, Backend, ComboQuery, Format},
+    error::CrawlError,
+    spellbook_api::{page_urls, SpellbookClient, VariantPage},
+    storage::Storage,
+    web_page::{self, CacheMode, WebPage},
+};
+use std::collections::VecDeque;
+
+/// Where the rendered search pages live.
+pub const SPELLBOOK_SEARCH_URL: &str = "https://commanderspellbook.com/search/";
+
+/// Only caches search pages that came from Commander Spellbook, not error pages in front of it.
+pub fn check_search_page(page: &WebPage) -> Result<(), String> {
+    web_page::check_page(page)?;
+    if !page.html_body.contains("Commander Spellbook") {
+        return Err("not a Commander Spellbook page".to_string());
+    }
+    Ok(())
+}
+
+/// Commander Spellbook, read either through its JSON API or its rendered search pages.
+pub struct Spellbook {
+    backend: Backend,
+    client: SpellbookClient,
+    search_url: String,
+}
+impl Spellbook {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            client: SpellbookClient::new(),
+            search_url: SPELLBOOK_SEARCH_URL.to_string(),
+        }
+    }
+
+    /// Reads from the JSON API of a different host, such as a local stand-in server.
+    #[cfg(test)]
+    pub fn with_client(client: SpellbookClient) -> Self {
+        Self {
+            client,
+            ..Self::new(Backend::Api)
+        }
+    }
+
+    /// Scrapes the search pages of a different host, such as a `FakeSpellbook`.
+    pub fn with_search_url(search_url: &str) -> Self {
+        Self {
+            search_url: search_url.to_string(),
+            ..Self::new(Backend::Html)
+        }
+    }
+}
+
+impl ComboSource for Spellbook {
+    fn name(&self) -> &str {
+        "commanderspellbook"
+    }
+
+    fn search<'a>(&'a self, query: &ComboQuery, storage: &Storage) -> ComboStream<'a> {
+        let cache = query.cache;
+        match self.backend {
+            Backend::Api => {
+                let query = commander_spellbook_query(
+                    query.colors,
+                    &query.required_cards,
+                    &query.excluded_cards,
+                    query.format,
+                    query.card_number,
+                    &query.outcomes,
+                );
+                Box::new(ApiPages {
+                    client: &self.client,
+                    storage: storage.clone(),
+                    cache,
+                    next: Some(self.client.variants_url(&query)),
+                    count: 0,
+                    pending: VecDeque::new(),
+                    combos: VecDeque::new(),
+                    error: None,
+                })
+            }
+            Backend::Html => {
+                let search = commander_spellbook_search(
+                    query.colors,
+                    &query.required_cards,
+                    &query.excluded_cards,
+                    query.format,
+                    query.card_number,
+                    &query.outcomes,
+                )
+                .replacen(SPELLBOOK_SEARCH_URL, &self.search_url, 1);
+                Box::new(HtmlPages {
+                    search,
+                    format: query.format,
+                    storage: storage.clone(),
+                    cache,
+                    page: Some(1),
+                    combos: VecDeque::new(),
+                })
+            }
+        }
+    }
+}
+
+/// Walks the JSON API. The first page says how many results there are, after which
+/// the remaining pages are fetched a batch at a time through the shared scheduler.
+/// Each page's `next` link is checked against the page worked out to come after it, and
+/// if they differ the links are followed one by one from there instead.
+struct ApiPages<'a> {
+    client: &'a SpellbookClient,
+    storage: Storage,
+    cache: CacheMode,
+    next: Option<String>,
+    /// How many results the first page said there were.
+    count: u32,
+    pending: VecDeque<String>,
+    combos: VecDeque<Combo>,
+    /// A failure to report once the combos fetched before it have been.
+    error: Option<CrawlError>,
+}
+impl Iterator for ApiPages<'_> {
+    type Item = Result<Combo, CrawlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.combos.is_empty() {
+            if let Some(e) = self.error.take() {
+                return Some(Err(e));
+            }
+            if self.pending.is_empty() {
+                let url = self.next.take()?;
+                let page = match self.client.fetch_page(&self.storage, &url, self.cache) {
+                    Ok(page) => page,
+                    Err(e) => return Some(Err(e)),
+                };
+                self.add_page(&page);
+                self.count = page.count;
+                if let Some(next) = page.next.as_ref() {
+                    self.pending.extend(page_urls(next, page.count));
+                }
+                continue;
+            }
+
+            let batch_size = self.storage.scheduler().workers().min(self.pending.len());
+            let urls: Vec<String> = self.pending.drain(..batch_size).collect();
+            let pages = self.client.fetch_pages(&self.storage, &urls, self.cache);
+            for (i, page) in pages.into_iter().enumerate() {
+                match page {
+                    Ok(page) => {
+                        self.add_page(&page);
+                        // If the page size or the results changed since the first page, the
+                        // pages worked out from it would skip or repeat combos, so the links
+                        // are followed from here instead. This is also how the crawl carries
+                        // on past the last page worked out.
+                        let expected = urls.get(i + 1).or(self.pending.front());
+                        if page.next.as_ref() != expected || page.count != self.count {
+                            self.pending.clear();
+                            self.next = page.next;
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        // Combos from the pages before the failure are still reported
+                        self.pending.clear();
+                        self.next = None;
+                        self.error = Some(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.combos.pop_front().map(Ok)
+    }
+}
+impl ApiPages<'_> {
+    fn add_page(&mut self, page: &VariantPage) {
+        for variant in page.results.iter() {
+            if !variant.uses.is_empty() {
+                self.combos.push_back(Combo::from_variant(variant));
+            }
+        }
+    }
+}
+
+/// Scrapes the rendered search pages one page at a time.
+struct HtmlPages {
+    search: String,
+    /// The format the search asked the site for, which every combo it finds is legal in.
+    format: Option<Format>,
+    storage: Storage,
+    cache: CacheMode,
+    page: Option<u32>,
+    combos: VecDeque<Combo>,
+}
+impl Iterator for HtmlPages {
+    type Item = Result<Combo, CrawlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.combos.is_empty() {
+            let page = self.page.take()?;
+            let web_page = match WebPage::fetch(
+                &self.storage,
+                &format!("{}&page={}", self.search, page),
+                self.cache,
+            ) {
+                Ok(web_page) => web_page,
+                Err(e) => return Some(Err(e)),
+            };
+            let document = web_page.document();
+
+            // Parse the HTML from commander spellbook
+            let selector = scraper::Selector::parse("div.py-1").unwrap();
+            for element in document.select(&selector) {
+                let name_selector = scraper::Selector::parse("div.card-name span").unwrap();
+                let mut cards = vec![];
+                for name_element in element.select(&name_selector) {
+                    let name = name_element.text().collect::<Vec<_>>().join("");
+                    cards.push(name);
+                }
+
+                if !cards.is_empty() {
+                    let mut combo = Combo {
+                        legal_formats: self.format.into_iter().collect(),
+                        ..Combo::new(cards)
+                    };
+                    // The rendered pages only link to the combo, the rest of its details live there
+                    let link_selector = scraper::Selector::parse("a[href*='/combo/']").unwrap();
+                    if let Some(href) = element
+                        .select(&link_selector)
+                        .next()
+                        .and_then(|link| link.value().attr("href"))
+                    {
+                        combo.id = href
+                            .trim_end_matches('/')
+                            .rsplit('/')
+                            .next()
+                            .map(|id| id.to_string());
+                        combo.url = Some(format!("https://commanderspellbook.com{href}"));
+                    }
+                    self.combos.push_back(combo);
+                }
+            }
+
+            // fetch next page
+            let next_selector = scraper::Selector::parse("button.forward-button").unwrap();
+            if document.select(&next_selector).next().is_some() {
+                self.page = Some(page + 1);
+            }
+        }
+
+        self.combos.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fetch::{FetchScheduler, Request, Response},
+        spellbook_api::tests::stand_in_server,
+    };
+
+    #[test]
+    fn api_search_collects_every_page() {
+        let source = Spellbook::with_client(SpellbookClient::with_base_url(&stand_in_server()));
+
+        let combos: Vec<Vec<String>> = source
+            .search(&ComboQuery::default(), &Storage::open_in_memory().unwrap())
+            .map(|combo| combo.unwrap().cards)
+            .collect();
+
+        assert_eq!(
+            vec![
+                vec!["Basalt Monolith", "Rings of Brighthearth"],
+                vec!["Rings of Brighthearth", "Grim Monolith"],
+                vec!["Basalt Monolith", "Forsaken Monument"],
+            ],
+            combos
+        );
+    }
+
+    #[test]
+    fn api_search_follows_links_when_the_page_size_changes() {
+        // Six combos, served at most two to a page at first and one to a page after that
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let scheduler = {
+            let requests = requests.clone();
+            FetchScheduler::with_transport(4, move |request: &Request| {
+                let mut requests = requests.lock().unwrap();
+                *requests += 1;
+                let url = reqwest::Url::parse(&request.url).unwrap();
+                let param = |name: &str| {
+                    url.query_pairs()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.parse::<usize>().unwrap())
+                };
+                let limit = param("limit")
+                    .unwrap_or(2)
+                    .min(if *requests == 1 { 2 } else { 1 });
+                let offset = param("offset").unwrap_or(0);
+                let results: Vec<String> = (offset..6.min(offset + limit))
+                    .map(|i| format!("{{\"id\": \"{i}\", \"uses\": [{{\"card\": {{\"name\": \"Card {i}\"}}}}]}}"))
+                    .collect();
+                let next = if offset + limit < 6 {
+                    format!(
+                        "\"https://api.test/variants/?limit={limit}&offset={}&q=ci%3Ac\"",
+                        offset + limit
+                    )
+                } else {
+                    "null".to_string()
+                };
+                Ok(Response::ok(&format!(
+                    "{{\"count\": 6, \"next\": {next}, \"results\": [{}]}}",
+                    results.join(", ")
+                )))
+            })
+        };
+        let storage = Storage::open_in_memory().unwrap().with_scheduler(scheduler);
+        let source = Spellbook::with_client(SpellbookClient::with_base_url("https://api.test"));
+
+        let ids: Vec<String> = source
+            .search(&ComboQuery::default(), &storage)
+            .map(|combo| combo.unwrap().id.unwrap())
+            .collect();
+        assert_eq!(vec!["0", "1", "2", "3", "4", "5"], ids);
+    }
+}
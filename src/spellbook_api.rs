@@ -0,0 +1,331 @@
+use crate::{
+    error::CrawlError,
+    storage::Storage,
+    web_page::{self, CacheMode, WebPage},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const SPELLBOOK_API_URL: &str = "https://backend.commanderspellbook.com";
+
+/// A single page of results from the `variants` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VariantPage {
+    pub count: u32,
+    pub next: Option<String>,
+    pub results: Vec<Variant>,
+}
+
+/// A combo variant as returned by the Commander Spellbook API.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Variant {
+    pub id: String,
+    #[serde(default)]
+    pub uses: Vec<CardUse>,
+    #[serde(default)]
+    pub requires: Vec<TemplateUse>,
+    #[serde(default)]
+    pub produces: Vec<Production>,
+    #[serde(default)]
+    pub identity: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub easy_prerequisites: String,
+    #[serde(default)]
+    pub notable_prerequisites: String,
+    #[serde(default)]
+    pub popularity: Option<u32>,
+    /// Keyed by format keyword, e.g. `commander`.
+    #[serde(default)]
+    pub legalities: HashMap<String, bool>,
+}
+impl Variant {
+    pub fn card_names(&self) -> Vec<String> {
+        self.uses
+            .iter()
+            .map(|card_use| card_use.card.name.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardUse {
+    pub card: ApiCard,
+    #[serde(default)]
+    pub zone_locations: Vec<String>,
+    #[serde(default)]
+    pub must_be_commander: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiCard {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateUse {
+    pub template: Template,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Template {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Production {
+    pub feature: Feature,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Feature {
+    pub name: String,
+}
+
+/// Client for the Commander Spellbook JSON API.
+pub struct SpellbookClient {
+    base_url: String,
+}
+impl SpellbookClient {
+    pub fn new() -> Self {
+        Self {
+            base_url: SPELLBOOK_API_URL.to_string(),
+        }
+    }
+
+    /// Creates a client that talks to a different host, used to point the crawler at a
+    /// local stand-in server. Pages are cached as usual.
+    #[cfg(test)]
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Returns the url of the first page of variants for an already encoded query.
+    pub fn variants_url(&self, query: &str) -> String {
+        format!("{}/variants/?q={}", self.base_url, query)
+    }
+
+    pub fn fetch_page(
+        &self,
+        storage: &Storage,
+        url: &str,
+        cache: CacheMode,
+    ) -> Result<VariantPage, CrawlError> {
+        self.fetch_pages(storage, &[url.to_string()], cache)
+            .remove(0)
+    }
+
+    /// Fetches several pages at once through the shared scheduler, returning them in the same order.
+    pub fn fetch_pages(
+        &self,
+        storage: &Storage,
+        urls: &[String],
+        cache: CacheMode,
+    ) -> Vec<Result<VariantPage, CrawlError>> {
+        urls.iter()
+            .zip(WebPage::fetch_all(storage, urls, cache))
+            .map(|(url, page)| {
+                serde_json::from_str(&page?.html_body).map_err(|e| CrawlError::Parse {
+                    source: url.clone(),
+                    message: e.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Only caches pages of variants, not throttling or error messages from the API, nor
+/// pages cut off part way through.
+pub fn check_variant_page(page: &WebPage) -> Result<(), String> {
+    web_page::check_page(page)?;
+    serde_json::from_str::<VariantPage>(&page.html_body)
+        .map_err(|e| format!("no variants in the response: {e}"))?;
+    Ok(())
+}
+
+/// The urls of every page after the first, worked out from its `next` link and the total count,
+/// so that they can all be fetched at once instead of following the links one by one.
+/// Returns just the `next` link if it doesn't page with `limit` and `offset`. The pages'
+/// own `next` links should still be checked against these, in case the results change.
+pub fn page_urls(next: &str, count: u32) -> Vec<String> {
+    let param = |name: &str| -> Option<u32> {
+        let url = reqwest::Url::parse(next).ok()?;
+        let (_, value) = url.query_pairs().find(|(key, _)| key == name)?;
+        value.parse().ok()
+    };
+    let (Some(limit), Some(offset)) = (param("limit"), param("offset")) else {
+        return vec![next.to_string()];
+    };
+    let offset_param = format!("offset={offset}");
+    if limit == 0 || next.matches(&offset_param).count() != 1 {
+        return vec![next.to_string()];
+    }
+
+    (offset..count)
+        .step_by(limit as usize)
+        .map(|page_offset| next.replace(&offset_param, &format!("offset={page_offset}")))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::fetch::{Request, Response};
+    use std::io::{BufRead, BufReader, Write};
+
+    pub const VARIANTS_PAGE_1: &str =
+        include_str!("../tests/fixtures/spellbook_variants_page1.json");
+    pub const VARIANTS_PAGE_2: &str =
+        include_str!("../tests/fixtures/spellbook_variants_page2.json");
+
+    /// Starts a local server that serves the recorded variant pages, rewriting
+    /// the pagination links to point back at itself. Returns the base url.
+    pub fn stand_in_server() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server_url = base_url.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+
+                let body = if request_line.contains("offset=2") {
+                    VARIANTS_PAGE_2
+                } else {
+                    VARIANTS_PAGE_1
+                };
+                let body = body.replace(SPELLBOOK_API_URL, &server_url);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        base_url
+    }
+
+    #[test]
+    fn parses_recorded_variants() {
+        let page: VariantPage = serde_json::from_str(VARIANTS_PAGE_1).unwrap();
+        assert_eq!(3, page.count);
+        assert!(page.next.is_some());
+        assert_eq!(2, page.results.len());
+
+        let variant = &page.results[0];
+        assert_eq!("1414-2730-5131-5256", variant.id);
+        assert_eq!(
+            vec!["Basalt Monolith", "Rings of Brighthearth"],
+            variant.card_names()
+        );
+        assert_eq!("C", variant.identity);
+        assert_eq!("Infinite colorless mana", variant.produces[0].feature.name);
+    }
+
+    #[test]
+    fn works_out_page_urls() {
+        let next = "https://backend.commanderspellbook.com/variants/?limit=2&offset=2&q=ci%3Ac";
+        assert_eq!(
+            vec![
+                next.to_string(),
+                next.replace("offset=2", "offset=4"),
+                next.replace("offset=2", "offset=6"),
+            ],
+            page_urls(next, 7)
+        );
+        assert_eq!(vec![next.to_string()], page_urls(next, 3));
+
+        let cursor = "https://backend.commanderspellbook.com/variants/?cursor=abc";
+        assert_eq!(vec![cursor.to_string()], page_urls(cursor, 100));
+    }
+
+    #[test]
+    fn follows_pagination_links() {
+        let client = SpellbookClient::with_base_url(&stand_in_server());
+        let storage = Storage::open_in_memory().unwrap();
+
+        let mut next = Some(client.variants_url("ci%3Ac"));
+        let mut ids = vec![];
+        while let Some(url) = next {
+            let page = client.fetch_page(&storage, &url, CacheMode::Fresh).unwrap();
+            ids.extend(page.results.iter().map(|variant| variant.id.clone()));
+            next = page.next;
+        }
+
+        assert_eq!(
+            vec!["1414-2730-5131-5256", "2730-5131-5256", "1414-5250"],
+            ids
+        );
+    }
+
+    #[test]
+    fn caches_and_revalidates_api_pages() {
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let scheduler = {
+            let sent = sent.clone();
+            crate::fetch::FetchScheduler::with_transport(2, move |request: &Request| {
+                sent.lock().unwrap().push(request.clone());
+                if request.etag.as_deref() == Some("\"v1\"") {
+                    return Ok(Response {
+                        status: 304,
+                        ..Response::ok("")
+                    });
+                }
+                Ok(Response {
+                    etag: Some("\"v1\"".to_string()),
+                    ..Response::ok(VARIANTS_PAGE_2)
+                })
+            })
+        };
+        let storage = Storage::open_in_memory().unwrap().with_scheduler(scheduler);
+        let client = SpellbookClient::with_base_url("https://api.test");
+        let url = client.variants_url("ci%3Ac");
+        let ids = |page: VariantPage| -> Vec<String> {
+            page.results.into_iter().map(|variant| variant.id).collect()
+        };
+
+        let first = client.fetch_page(&storage, &url, CacheMode::Fresh).unwrap();
+        assert_eq!(vec!["1414-5250"], ids(first));
+        assert!(storage.cached_page(&url).unwrap().is_some());
+
+        // Fresh pages come from the cache, even offline
+        let cached = client.fetch_page(&storage, &url, CacheMode::Fresh).unwrap();
+        assert_eq!(vec!["1414-5250"], ids(cached));
+        let offline = client
+            .fetch_page(&storage, &url, CacheMode::Offline)
+            .unwrap();
+        assert_eq!(vec!["1414-5250"], ids(offline));
+        assert_eq!(1, sent.lock().unwrap().len());
+
+        // A refresh asks whether the page changed, and keeps the cached copy when it hasn't
+        let refreshed = client
+            .fetch_page(&storage, &url, CacheMode::Refresh)
+            .unwrap();
+        assert_eq!(vec!["1414-5250"], ids(refreshed));
+        assert_eq!(
+            Some("\"v1\"".to_string()),
+            sent.lock().unwrap()[1].etag.clone()
+        );
+
+        let uncached = client.variants_url("ci%3Aw");
+        assert_eq!(
+            Err(CrawlError::NotCached {
+                url: uncached.clone()
+            }),
+            client
+                .fetch_page(&storage, &uncached, CacheMode::Offline)
+                .map(ids)
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use crate::{
+    combo::Combo,
+    crawler::{ComboQuery, Format},
+    error::CrawlError,
+    fetch::{self, FetchScheduler},
+    query::Comparison,
+    web_page::WebPage,
+};
+use std::{ops::Deref, sync::Arc, time::Duration};
+
+mod postgres;
+mod sqlite;
+
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+/// A combo with the details only a bulk export has.
+#[derive(Debug, Clone)]
+pub struct StoredCombo {
+    pub combo: Combo,
+    pub popularity: Option<u32>,
+    pub legal_formats: Vec<Format>,
+}
+
+/// How big the cache is and how well it's working.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Space the whole database takes up.
+    pub database_bytes: u64,
+    pub pages: u64,
+    /// Space the page bodies take up.
+    pub page_bytes: u64,
+    pub combos: u64,
+    pub searches: u64,
+    /// Pages served from the cache, including ones the site said hadn't changed.
+    pub hits: u64,
+    /// Pages that had to be downloaded, or couldn't be found offline.
+    pub misses: u64,
+}
+impl CacheStats {
+    /// The share of pages served from the cache, if any have been asked for.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+/// A cached page without its body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageSummary {
+    pub id: i32,
+    pub url: String,
+    pub fetched_at: Option<i64>,
+    pub bytes: u64,
+}
+
+/// A saved search without its combos.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchSummary {
+    pub id: i32,
+    pub key: String,
+    pub searched_at: i64,
+    pub combos: u64,
+}
+
+/// A database holding the page cache, combos and saved searches. Every method runs in its
+/// own transaction, so a store can be shared by any number of searches at once.
+pub trait Store: Send + Sync {
+    /// The cached copy of a page. A page stored before bodies were compressed is compressed
+    /// as it's read.
+    fn cached_page(&self, url: &str) -> Result<Option<WebPage>, CrawlError>;
+    /// Caches a page with its body compressed, replacing any earlier copy. Returns its id.
+    fn store_page(&self, page: &WebPage) -> Result<i32, CrawlError>;
+    /// Records that a cached page was revalidated.
+    fn touch_page(&self, id: i32, fetched_at: i64) -> Result<(), CrawlError>;
+    #[allow(
+        dead_code,
+        reason = "commands go through page_summaries, tests read every page back"
+    )]
+    fn pages(&self) -> Result<Vec<WebPage>, CrawlError>;
+    /// The cached pages with these ids, in no particular order.
+    fn pages_by_id(&self, ids: &[i32]) -> Result<Vec<WebPage>, CrawlError>;
+    fn delete_page(&self, id: i32) -> Result<(), CrawlError>;
+
+    /// Stores combos from a bulk export, replacing any combos with the same id.
+    fn import_combos(&self, combos: &[StoredCombo]) -> Result<(), CrawlError>;
+    #[allow(
+        dead_code,
+        reason = "searches go through find_combos, tests read combos back by id"
+    )]
+    fn read_combo(&self, id: &str) -> Result<Option<Combo>, CrawlError>;
+    /// Imported combos matching the query's cards, format and card count, most popular
+    /// first. Their color identity isn't checked, and combos only a search found are left out.
+    fn find_combos(&self, query: &ComboQuery) -> Result<Vec<Combo>, CrawlError>;
+
+    /// Stores the combos a search found, in order, under `key`. Combos must have an id.
+    /// A combo that's already stored is only replaced by a copy with results or steps.
+    /// The formats the combos' sources said they're legal in are added to the stored ones.
+    fn save_search(&self, key: &str, combos: &[Combo]) -> Result<(), CrawlError>;
+    /// The combos saved under `key` with their sources, if they were saved less than `max_age` ago.
+    fn load_search(&self, key: &str, max_age: Duration) -> Result<Option<Vec<Combo>>, CrawlError>;
+
+    /// Adds to the counts of pages served from the cache and pages that weren't.
+    fn record_lookups(&self, hits: u64, misses: u64) -> Result<(), CrawlError>;
+    fn cache_stats(&self) -> Result<CacheStats, CrawlError>;
+    /// Every cached page, without reading the bodies.
+    fn page_summaries(&self) -> Result<Vec<PageSummary>, CrawlError>;
+    fn searches(&self) -> Result<Vec<SearchSummary>, CrawlError>;
+    /// Forgets a saved search. Its combos stay, other searches may have found them too.
+    fn delete_search(&self, id: i32) -> Result<(), CrawlError>;
+    /// Compresses any pages still stored as plain text, gives the space left by deleted rows
+    /// back and refreshes the query planner's statistics.
+    fn vacuum(&self) -> Result<(), CrawlError>;
+}
+
+/// The store every search shares. Clones share the same connections.
+#[derive(Clone)]
+pub struct Storage {
+    store: Arc<dyn Store>,
+    /// Where pages missing from the store are fetched, if not the shared scheduler.
+    scheduler: Option<Arc<FetchScheduler>>,
+}
+impl Storage {
+    pub fn new(store: impl Store + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            scheduler: None,
+        }
+    }
+
+    /// Fetches pages with `scheduler` instead of the shared one, so that a test can crawl
+    /// against a recording without touching the network.
+    #[cfg(test)]
+    pub fn with_scheduler(mut self, scheduler: FetchScheduler) -> Self {
+        self.scheduler = Some(Arc::new(scheduler));
+        self
+    }
+
+    pub fn scheduler(&self) -> &FetchScheduler {
+        match self.scheduler.as_deref() {
+            Some(scheduler) => scheduler,
+            None => fetch::scheduler(),
+        }
+    }
+
+    /// Opens the database at `url`. `postgres://` and `postgresql://` urls connect to a Postgres
+    /// server, so that a team can share one cache, and anything else is a SQLite file, e.g.
+    /// `ccb.sqlite` or `sqlite://ccb.sqlite`.
+    pub fn open(url: &str) -> Result<Self, CrawlError> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Ok(Self::new(PostgresStore::open(url)?))
+        } else {
+            let path = url.strip_prefix("sqlite://").unwrap_or(url);
+            Ok(Self::new(SqliteStore::open(path)?))
+        }
+    }
+
+    /// A private SQLite database that lasts as long as the handle, for tests.
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, CrawlError> {
+        Ok(Self::new(SqliteStore::open_in_memory()?))
+    }
+}
+impl Deref for Storage {
+    type Target = dyn Store;
+
+    fn deref(&self) -> &Self::Target {
+        self.store.as_ref()
+    }
+}
+
+/// The columns pages are read from, in the order `WebPage` is built from, followed by how
+/// the body is stored.
+const PAGE_COLUMNS: &str = "id, url, html_body, status, content_type, fetched_at, etag, \
+    last_modified, body_format, compressed_body";
+
+/// How a page body is kept in the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFormat {
+    /// Text in `html_body`. Pages cached before bodies were compressed stay like this until
+    /// they're next read, or the cache is vacuumed.
+    Plain = 0,
+    /// Zstandard compressed bytes in `compressed_body`, with `html_body` left empty.
+    Zstd = 1,
+}
+
+/// Compression level for page bodies. Pages are written once and read many times, and
+/// the higher levels only save a little more on HTML for a lot more time.
+const BODY_COMPRESSION_LEVEL: i32 = 3;
+
+/// Compresses a page body the way `Store::store_page` keeps it.
+pub fn compress_body(body: &str) -> Result<Vec<u8>, CrawlError> {
+    zstd::encode_all(body.as_bytes(), BODY_COMPRESSION_LEVEL)
+        .map_err(|e| CrawlError::Database(format!("Unable to compress a page: {e}")))
+}
+
+/// The text of a body stored in `format`, given the `html_body` and `compressed_body` columns.
+fn read_body(
+    format: i32,
+    html_body: String,
+    compressed_body: Option<Vec<u8>>,
+) -> Result<(String, BodyFormat), CrawlError> {
+    match format {
+        0 => Ok((html_body, BodyFormat::Plain)),
+        1 => {
+            let bytes = zstd::decode_all(compressed_body.unwrap_or_default().as_slice())
+                .map_err(|e| CrawlError::Database(format!("Unable to decompress a page: {e}")))?;
+            let body = String::from_utf8(bytes)
+                .map_err(|e| CrawlError::Database(format!("A stored page isn't text: {e}")))?;
+            Ok((body, BodyFormat::Zstd))
+        }
+        format => Err(CrawlError::Database(format!(
+            "A page is stored in format {format}, which this build doesn't know"
+        ))),
+    }
+}
+
+/// A value bound into a combo search, converted to each backend's own parameter type.
+enum Param {
+    Text(String),
+    Int(i32),
+}
+
+/// The `WHERE` clause for `Store::find_combos` on the `combos c` table. `placeholder` gives
+/// the backend's syntax for the nth parameter and `like` its case insensitive `LIKE`.
+fn combo_filters(
+    query: &ComboQuery,
+    placeholder: impl Fn(usize) -> String,
+    like: &str,
+) -> (String, Vec<Param>) {
+    let mut filters = vec!["c.imported".to_string()];
+    let mut params = vec![];
+    let cards = query
+        .required_cards
+        .iter()
+        .map(|card| ("EXISTS", card))
+        .chain(query.excluded_cards.iter().map(|card| ("NOT EXISTS", card)));
+    for (exists, card) in cards {
+        params.push(Param::Text(card.trim().to_string()));
+        filters.push(format!(
+            "{exists} (SELECT 1 FROM combo_cards fc JOIN cards fk ON fk.id = fc.card_id
+                WHERE fc.combo_id = c.id AND fk.name {like} '%' || {} || '%')",
+            placeholder(params.len())
+        ));
+    }
+    if let Some(format) = query.format {
+        params.push(Param::Text(format.keyword().to_string()));
+        filters.push(format!(
+            "EXISTS (SELECT 1 FROM combo_legalities l WHERE l.combo_id = c.id AND l.format = {})",
+            placeholder(params.len())
+        ));
+    }
+    for (comparison, count) in query.card_number.comparisons() {
+        let operator = match comparison {
+            Comparison::Equal => "=",
+            comparison => comparison.symbol(),
+        };
+        params.push(Param::Int(count as i32));
+        filters.push(format!(
+            "c.card_count {operator} {}",
+            placeholder(params.len())
+        ));
+    }
+
+    (format!("WHERE {}", filters.join(" AND ")), params)
+}
+
+/// Whether a combo found by a search should replace the stored copy. Sources that only
+/// list the cards, like EDHREC's pages, don't wipe out the details of one that's stored.
+fn replaces_stored(combo: &Combo) -> bool {
+    !combo.results.is_empty() || !combo.steps.is_empty()
+}
+
+/// Seconds since the Unix epoch, which is how fetch and search times are stored.
+pub(crate) fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{bulk_data, crawler::CardNumber};
+
+    fn page(url: &str, body: &str) -> WebPage {
+        WebPage {
+            id: 0,
+            url: url.to_string(),
+            html_body: body.to_string(),
+            status: Some(200),
+            content_type: Some("text/html".to_string()),
+            fetched_at: Some(1000),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        }
+    }
+
+    /// Runs every part of a store, so that each backend is held to the same behaviour.
+    pub fn exercise_store(storage: &Storage) {
+        // Pages
+        let mut stored = page("https://edhrec.com/combos/sol-ring", "<p>v1</p>");
+        stored.id = storage.store_page(&stored).unwrap();
+        storage.touch_page(stored.id, 2000).unwrap();
+        let cached = storage.cached_page(&stored.url).unwrap().unwrap();
+        assert_eq!(Some(2000), cached.fetched_at);
+        assert_eq!(stored.etag, cached.etag);
+        assert_eq!(Some(200), cached.status);
+
+        let id = storage.store_page(&page(&stored.url, "<p>v2</p>")).unwrap();
+        let other = storage
+            .store_page(&page("https://edhrec.com/combos/mana-crypt", "<p>v1</p>"))
+            .unwrap();
+        let by_id = storage.pages_by_id(&[other, -1]).unwrap();
+        assert_eq!(
+            vec!["https://edhrec.com/combos/mana-crypt"],
+            by_id
+                .iter()
+                .map(|page| page.url.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("<p>v1</p>", by_id[0].html_body);
+        assert!(storage.pages_by_id(&[]).unwrap().is_empty());
+        assert_eq!(
+            "<p>v2</p>",
+            storage.cached_page(&stored.url).unwrap().unwrap().html_body
+        );
+        assert_eq!(2, storage.pages().unwrap().len());
+        storage.delete_page(id).unwrap();
+        assert!(storage.cached_page(&stored.url).unwrap().is_none());
+
+        // Combos
+        bulk_data::import(
+            std::path::Path::new("tests/fixtures/spellbook_bulk_variants.json"),
+            storage,
+        )
+        .unwrap();
+        let combo = storage.read_combo("2117-4321-5555").unwrap().unwrap();
+        assert_eq!(
+            vec![
+                "Incubation Druid",
+                "Staff of Domination",
+                "Nyxbloom Ancient"
+            ],
+            combo.cards
+        );
+        assert_eq!(
+            vec!["Infinite colored mana", "Infinite card draw"],
+            combo.results
+        );
+        assert!(storage.read_combo("missing").unwrap().is_none());
+
+        let find = |query: ComboQuery| storage.find_combos(&query).unwrap().len();
+        assert_eq!(4, find(ComboQuery::default()));
+        assert_eq!(
+            1,
+            find(ComboQuery {
+                required_cards: vec!["basalt".to_string(), "Rings".to_string()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            3,
+            find(ComboQuery {
+                excluded_cards: vec!["Forsaken Monument".to_string()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            3,
+            find(ComboQuery {
+                format: Some(Format::Commander),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            1,
+            find(ComboQuery {
+                card_number: CardNumber::GreaterThan(2),
+                ..Default::default()
+            })
+        );
+
+        // Saved searches, where a sparse copy of a stored combo keeps its details
+        let sparse = Combo {
+            id: Some("2117-4321-5555".to_string()),
+            sources: vec!["edhrec".to_string()],
+            ..Combo::new(combo.cards.clone())
+        };
+        let found = Combo {
+            id: Some("cards:Basalt Monolith+Forsaken Monument".to_string()),
+            sources: vec!["edhrec".to_string(), "local".to_string()],
+            ..Combo::new(vec![
+                "Basalt Monolith".to_string(),
+                "Forsaken Monument".to_string(),
+            ])
+        };
+        let day = Duration::from_secs(24 * 60 * 60);
+        storage
+            .save_search("key", &[found, sparse.clone()])
+            .unwrap();
+        // Combos only a search found aren't offered as imported ones
+        assert_eq!(4, find(ComboQuery::default()));
+        // and keep not knowing their identity
+        let combos = storage.load_search("key", day).unwrap().unwrap();
+        assert_eq!(None, combos[0].color_identity);
+        assert!(combos[1].color_identity.is_some());
+        // Only the formats a source said a combo is legal in are recorded, not the search's
+        let pauper = Combo {
+            legal_formats: vec![Format::PauperCommander],
+            ..sparse
+        };
+        storage.save_search("key", &[pauper]).unwrap();
+        let legal = |format: Format| -> Vec<Option<String>> {
+            storage
+                .find_combos(&ComboQuery {
+                    format: Some(format),
+                    ..Default::default()
+                })
+                .unwrap()
+                .into_iter()
+                .map(|combo| combo.id)
+                .collect()
+        };
+        assert_eq!(3, legal(Format::Commander).len());
+        assert_eq!(
+            vec![Some("2117-4321-5555".to_string())],
+            legal(Format::PauperCommander)
+        );
+        let combos = storage.load_search("key", day).unwrap().unwrap();
+        assert_eq!(1, combos.len());
+        assert_eq!(
+            vec!["Infinite colored mana", "Infinite card draw"],
+            combos[0].results
+        );
+        assert_eq!(vec!["edhrec"], combos[0].sources);
+        assert!(storage.load_search("other", day).unwrap().is_none());
+        assert!(storage
+            .load_search("key", Duration::ZERO)
+            .unwrap()
+            .is_none());
+
+        // Cache administration
+        storage.record_lookups(3, 0).unwrap();
+        storage.record_lookups(0, 1).unwrap();
+        let stats = storage.cache_stats().unwrap();
+        // Sizes are what the compressed bodies take up
+        let stored_bytes = compress_body("<p>v1</p>").unwrap().len() as u64;
+        assert_eq!((1, stored_bytes), (stats.pages, stats.page_bytes));
+        assert_eq!((5, 1), (stats.combos, stats.searches));
+        assert_eq!(Some(0.75), stats.hit_rate());
+        let pages = storage.page_summaries().unwrap();
+        assert_eq!("https://edhrec.com/combos/mana-crypt", pages[0].url);
+        assert_eq!(
+            (Some(1000), stored_bytes),
+            (pages[0].fetched_at, pages[0].bytes)
+        );
+        let searches = storage.searches().unwrap();
+        assert_eq!(("key", 1), (searches[0].key.as_str(), searches[0].combos));
+        storage.delete_search(searches[0].id).unwrap();
+        assert!(storage.load_search("key", day).unwrap().is_none());
+        storage.vacuum().unwrap();
+        assert_eq!(0, storage.cache_stats().unwrap().searches);
+
+        // Storing a combo again, from another search or an import, leaves the searches that
+        // already found it alone
+        let shared = Combo {
+            sources: vec!["commanderspellbook".to_string()],
+            ..combo.clone()
+        };
+        storage
+            .save_search("first", std::slice::from_ref(&shared))
+            .unwrap();
+        storage
+            .save_search("second", std::slice::from_ref(&shared))
+            .unwrap();
+        bulk_data::import(
+            std::path::Path::new("tests/fixtures/spellbook_bulk_variants.json"),
+            storage,
+        )
+        .unwrap();
+        for key in ["first", "second"] {
+            let combos = storage.load_search(key, day).unwrap().unwrap();
+            assert_eq!(
+                vec![shared.id.clone()],
+                combos
+                    .iter()
+                    .map(|combo| combo.id.clone())
+                    .collect::<Vec<_>>()
+            );
+            // Only with the sources this search found it through
+            assert_eq!(vec!["commanderspellbook"], combos[0].sources);
+            assert_eq!(shared.results, combos[0].results);
+        }
+    }
+
+    #[test]
+    fn sqlite_store() {
+        exercise_store(&Storage::open_in_memory().unwrap());
+    }
+}
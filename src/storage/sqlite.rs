@@ -0,0 +1,709 @@
+use super::{
+    combo_filters, compress_body, now, read_body, replaces_stored, BodyFormat, CacheStats,
+    PageSummary, Param, SearchSummary, Store, StoredCombo, PAGE_COLUMNS,
+};
+use crate::{
+    combo::{Combo, Zone, ZoneRequirement},
+    crawler::{ComboQuery, Format},
+    error::CrawlError,
+    migrations,
+    web_page::WebPage,
+};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OptionalExtension;
+use std::{path::Path, time::Duration};
+
+/// A connection borrowed from the pool. It goes back to the pool when dropped.
+pub type Connection = r2d2::PooledConnection<SqliteConnectionManager>;
+
+/// A SQLite database file. Clones share one pool of connections, so each search takes
+/// its own connection rather than queueing on a lock.
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+}
+impl SqliteStore {
+    /// Opens the database at `path`, creating it if needed and bringing its schema up to date.
+    /// It's put in WAL mode so that reading the cache never waits on another search writing to it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CrawlError> {
+        let manager = SqliteConnectionManager::file(path).with_init(|db| {
+            // Writers still take turns, so wait for the other one rather than failing
+            db.busy_timeout(Duration::from_secs(5))?;
+            db.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+        });
+        Self::with_manager(manager, 8)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, CrawlError> {
+        // Every connection to a plain in-memory database gets its own database, so the
+        // pool is limited to the one connection
+        Self::with_manager(SqliteConnectionManager::memory(), 1)
+    }
+
+    fn with_manager(manager: SqliteConnectionManager, size: u32) -> Result<Self, CrawlError> {
+        let pool = r2d2::Pool::builder()
+            .max_size(size)
+            .build(manager)
+            .map_err(|e| CrawlError::Database(e.to_string()))?;
+        let store = Self { pool };
+        let from = migrations::migrate(&mut *store.connection()?)?;
+        if from != 0 && from < migrations::latest_version() {
+            log::info!(
+                "Upgraded the database from schema version {} to {}",
+                from,
+                migrations::latest_version()
+            );
+        }
+        Ok(store)
+    }
+
+    /// Takes a connection from the pool, waiting if every connection is in use.
+    /// Hold it only as long as a transaction, and never across a network request.
+    pub fn connection(&self) -> Result<Connection, CrawlError> {
+        self.pool
+            .get()
+            .map_err(|e| CrawlError::Database(e.to_string()))
+    }
+}
+
+impl Store for SqliteStore {
+    fn cached_page(&self, url: &str) -> Result<Option<WebPage>, CrawlError> {
+        let db = self.connection()?;
+        let Some((page, format)) = db
+            .query_row(
+                &format!("SELECT {PAGE_COLUMNS} FROM html_page where url = (?1)"),
+                [url],
+                page_from_row,
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        if format == BodyFormat::Plain {
+            compress_page(&db, page.id, &page.html_body)?;
+        }
+        Ok(Some(page))
+    }
+
+    fn store_page(&self, page: &WebPage) -> Result<i32, CrawlError> {
+        let compressed = compress_body(&page.html_body)?;
+        let mut db = self.connection()?;
+        let tx = db.transaction()?;
+        tx.execute("DELETE FROM html_page WHERE url = (?1)", [&page.url])?;
+        tx.execute(
+            "INSERT INTO html_page (url, html_body, body_format, compressed_body, status,
+                content_type, fetched_at, etag, last_modified)
+            VALUES (?1, '', ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            rusqlite::params![
+                page.url,
+                BodyFormat::Zstd as i32,
+                compressed,
+                page.status,
+                page.content_type,
+                page.fetched_at,
+                page.etag,
+                page.last_modified
+            ],
+        )?;
+        let id = tx.last_insert_rowid() as i32;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    fn touch_page(&self, id: i32, fetched_at: i64) -> Result<(), CrawlError> {
+        self.connection()?.execute(
+            "UPDATE html_page SET fetched_at = (?1) WHERE id = (?2)",
+            (fetched_at, id),
+        )?;
+        Ok(())
+    }
+
+    fn pages(&self) -> Result<Vec<WebPage>, CrawlError> {
+        let pages = self
+            .connection()?
+            .prepare(&format!("SELECT {PAGE_COLUMNS} FROM html_page"))?
+            .query_map([], |row| page_from_row(row).map(|(page, _)| page))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(pages)
+    }
+
+    fn pages_by_id(&self, ids: &[i32]) -> Result<Vec<WebPage>, CrawlError> {
+        let db = self.connection()?;
+        let mut pages = vec![];
+        // SQLite limits how many parameters a statement can have
+        for ids in ids.chunks(500) {
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            let chunk = db
+                .prepare(&format!(
+                    "SELECT {PAGE_COLUMNS} FROM html_page WHERE id IN ({placeholders})"
+                ))?
+                .query_map(rusqlite::params_from_iter(ids), |row| {
+                    page_from_row(row).map(|(page, _)| page)
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            pages.extend(chunk);
+        }
+        Ok(pages)
+    }
+
+    fn delete_page(&self, id: i32) -> Result<(), CrawlError> {
+        self.connection()?
+            .execute("DELETE FROM html_page WHERE id = (?1)", [id])?;
+        Ok(())
+    }
+
+    fn import_combos(&self, combos: &[StoredCombo]) -> Result<(), CrawlError> {
+        let mut db = self.connection()?;
+        let tx = db.transaction()?;
+        for stored in combos {
+            insert_combo(
+                &tx,
+                &stored.combo,
+                stored.popularity,
+                &stored.legal_formats,
+                true,
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn read_combo(&self, id: &str) -> Result<Option<Combo>, CrawlError> {
+        Ok(read_combo(&*self.connection()?, id).optional()?)
+    }
+
+    fn find_combos(&self, query: &ComboQuery) -> Result<Vec<Combo>, CrawlError> {
+        let db = self.connection()?;
+        let (where_clause, params) = combo_filters(query, |n| format!("?{n}"), "LIKE");
+        let params: Vec<Box<dyn rusqlite::ToSql>> = params
+            .into_iter()
+            .map(|param| -> Box<dyn rusqlite::ToSql> {
+                match param {
+                    Param::Text(text) => Box::new(text),
+                    Param::Int(int) => Box::new(int),
+                }
+            })
+            .collect();
+        let ids = db
+            .prepare(&format!(
+                "SELECT c.id FROM combos c {where_clause} ORDER BY c.popularity DESC, c.id"
+            ))?
+            .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+                row.get::<_, String>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut combos = vec![];
+        for id in ids {
+            combos.push(read_combo(&db, &id)?);
+        }
+        Ok(combos)
+    }
+
+    fn save_search(&self, key: &str, combos: &[Combo]) -> Result<(), CrawlError> {
+        let mut db = self.connection()?;
+        let tx = db.transaction()?;
+
+        let search_id: i64 = tx.query_row(
+            "INSERT INTO searches (key, searched_at) VALUES (?1, ?2)
+            ON CONFLICT(key) DO UPDATE SET searched_at = excluded.searched_at
+            RETURNING id",
+            (key, now()),
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "DELETE FROM search_combos WHERE search_id = ?1",
+            [search_id],
+        )?;
+
+        for (position, combo) in combos.iter().enumerate() {
+            let id = combo.id.as_deref().unwrap_or_default();
+            let stored = tx
+                .query_row("SELECT popularity FROM combos WHERE id = ?1", [id], |row| {
+                    row.get::<_, Option<u32>>(0)
+                })
+                .optional()?;
+            if stored.is_none() || replaces_stored(combo) {
+                let mut legal_formats = vec![];
+                let mut stmt =
+                    tx.prepare_cached("SELECT format FROM combo_legalities WHERE combo_id = ?1")?;
+                for keyword in stmt.query_map([id], |row| row.get::<_, String>(0))? {
+                    legal_formats.extend(Format::from_keyword(&keyword?));
+                }
+                drop(stmt);
+                for format in combo.legal_formats.iter() {
+                    if !legal_formats.contains(format) {
+                        legal_formats.push(*format);
+                    }
+                }
+                insert_combo(&tx, combo, stored.flatten(), &legal_formats, false)?;
+            } else {
+                for format in combo.legal_formats.iter() {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO combo_legalities (combo_id, format) VALUES (?1, ?2)",
+                        (id, format.keyword()),
+                    )?;
+                }
+            }
+
+            tx.execute(
+                "INSERT OR IGNORE INTO search_combos (search_id, combo_id, position, sources)
+                VALUES (?1, ?2, ?3, ?4)",
+                (search_id, id, position as u32, combo.sources.join("\n")),
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_search(&self, key: &str, max_age: Duration) -> Result<Option<Vec<Combo>>, CrawlError> {
+        let db = self.connection()?;
+        let Some((search_id, searched_at)) = db
+            .query_row(
+                "SELECT id, searched_at FROM searches WHERE key = ?1",
+                [key],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        if now().saturating_sub(searched_at).max(0) as u64 >= max_age.as_secs() {
+            return Ok(None);
+        }
+
+        let found = db
+            .prepare(
+                "SELECT combo_id, sources FROM search_combos WHERE search_id = ?1 ORDER BY position",
+            )?
+            .query_map([search_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut combos = vec![];
+        for (id, sources) in found {
+            let mut combo = read_combo(&db, &id)?;
+            combo.sources = sources.lines().map(|source| source.to_string()).collect();
+            combos.push(combo);
+        }
+        Ok(Some(combos))
+    }
+
+    fn record_lookups(&self, hits: u64, misses: u64) -> Result<(), CrawlError> {
+        let mut db = self.connection()?;
+        let tx = db.transaction()?;
+        for (name, count) in [("hits", hits), ("misses", misses)] {
+            tx.execute(
+                "INSERT INTO cache_counters (name, value) VALUES (?1, ?2)
+                ON CONFLICT(name) DO UPDATE SET value = cache_counters.value + excluded.value",
+                (name, count as i64),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn cache_stats(&self) -> Result<CacheStats, CrawlError> {
+        let db = self.connection()?;
+        let count = |sql: &str| -> rusqlite::Result<u64> {
+            db.query_row(sql, [], |row| row.get::<_, Option<i64>>(0))
+                .map(|count| count.unwrap_or(0) as u64)
+        };
+        Ok(CacheStats {
+            database_bytes: count(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            )?,
+            pages: count("SELECT COUNT(*) FROM html_page")?,
+            page_bytes: count(&format!("SELECT SUM({STORED_BODY_BYTES}) FROM html_page"))?,
+            combos: count("SELECT COUNT(*) FROM combos")?,
+            searches: count("SELECT COUNT(*) FROM searches")?,
+            hits: count("SELECT value FROM cache_counters WHERE name = 'hits'")
+                .optional()?
+                .unwrap_or(0),
+            misses: count("SELECT value FROM cache_counters WHERE name = 'misses'")
+                .optional()?
+                .unwrap_or(0),
+        })
+    }
+
+    fn page_summaries(&self) -> Result<Vec<PageSummary>, CrawlError> {
+        let pages = self
+            .connection()?
+            .prepare(&format!(
+                "SELECT id, url, fetched_at, {STORED_BODY_BYTES} FROM html_page"
+            ))?
+            .query_map([], |row| {
+                Ok(PageSummary {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    fetched_at: row.get(2)?,
+                    bytes: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(pages)
+    }
+
+    fn searches(&self) -> Result<Vec<SearchSummary>, CrawlError> {
+        let searches = self
+            .connection()?
+            .prepare(
+                "SELECT s.id, s.key, s.searched_at, COUNT(sc.combo_id) FROM searches s
+                LEFT JOIN search_combos sc ON sc.search_id = s.id GROUP BY s.id",
+            )?
+            .query_map([], |row| {
+                Ok(SearchSummary {
+                    id: row.get(0)?,
+                    key: row.get(1)?,
+                    searched_at: row.get(2)?,
+                    combos: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(searches)
+    }
+
+    fn delete_search(&self, id: i32) -> Result<(), CrawlError> {
+        let mut db = self.connection()?;
+        let tx = db.transaction()?;
+        // Foreign keys aren't enforced, so nothing cascades
+        tx.execute("DELETE FROM search_combos WHERE search_id = ?1", [id])?;
+        tx.execute("DELETE FROM searches WHERE id = ?1", [id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn vacuum(&self) -> Result<(), CrawlError> {
+        let mut db = self.connection()?;
+        // Pages that haven't been read since bodies were compressed, so their space is given back too
+        let tx = db.transaction()?;
+        let plain = tx
+            .prepare("SELECT id FROM html_page WHERE body_format = ?1")?
+            .query_map([BodyFormat::Plain as i32], |row| row.get::<_, i32>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for id in plain {
+            let body: String = tx.query_row(
+                "SELECT html_body FROM html_page WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )?;
+            compress_page(&tx, id, &body)?;
+        }
+        tx.commit()?;
+
+        db.execute_batch("VACUUM; ANALYZE;")?;
+        // Empties the write-ahead log as well, so the file sizes on disk add up
+        db.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+}
+
+/// The space a page's body takes up, however it's stored.
+const STORED_BODY_BYTES: &str =
+    "length(CAST(html_body AS BLOB)) + IFNULL(length(compressed_body), 0)";
+
+fn page_from_row(row: &rusqlite::Row) -> rusqlite::Result<(WebPage, BodyFormat)> {
+    let (html_body, format) = read_body(row.get(8)?, row.get(2)?, row.get(9)?).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(9, rusqlite::types::Type::Blob, Box::new(e))
+    })?;
+    let page = WebPage {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        html_body,
+        status: row.get(3)?,
+        content_type: row.get(4)?,
+        fetched_at: row.get(5)?,
+        etag: row.get(6)?,
+        last_modified: row.get(7)?,
+    };
+    Ok((page, format))
+}
+
+/// Rewrites a page cached before bodies were compressed.
+fn compress_page(db: &rusqlite::Connection, id: i32, body: &str) -> Result<(), CrawlError> {
+    db.execute(
+        "UPDATE html_page SET html_body = '', body_format = ?1, compressed_body = ?2
+        WHERE id = ?3 AND body_format = ?4",
+        (
+            BodyFormat::Zstd as i32,
+            compress_body(body)?,
+            id,
+            BodyFormat::Plain as i32,
+        ),
+    )?;
+    Ok(())
+}
+
+/// Stores a combo, replacing any existing combo with the same id. Combos without an id are skipped.
+/// A combo stays `imported` once a bulk export has had it.
+fn insert_combo(
+    db: &rusqlite::Connection,
+    combo: &Combo,
+    popularity: Option<u32>,
+    legal_formats: &[Format],
+    imported: bool,
+) -> rusqlite::Result<()> {
+    let Some(id) = combo.id.as_ref() else {
+        return Ok(());
+    };
+
+    // The row is updated in place like on Postgres, where deleting it would cascade to the
+    // searches and sources that refer to it. Only its own details are replaced.
+    db.execute(
+        "INSERT INTO combos
+            (id, url, identity, card_count, prerequisites, steps, popularity, imported)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(id) DO UPDATE SET url = excluded.url, identity = excluded.identity,
+            card_count = excluded.card_count, prerequisites = excluded.prerequisites,
+            steps = excluded.steps, popularity = excluded.popularity,
+            imported = MAX(imported, excluded.imported)",
+        (
+            id,
+            &combo.url,
+            combo
+                .color_identity
+                .map(|identity| identity.symbols())
+                .unwrap_or_default(),
+            combo.cards.len() as u32,
+            combo.prerequisites.join("\n"),
+            combo.steps.join("\n"),
+            popularity,
+            imported,
+        ),
+    )?;
+
+    db.execute("DELETE FROM combo_cards WHERE combo_id = ?1", [id])?;
+    db.execute("DELETE FROM combo_results WHERE combo_id = ?1", [id])?;
+    db.execute("DELETE FROM combo_legalities WHERE combo_id = ?1", [id])?;
+
+    let mut insert_card =
+        db.prepare_cached("INSERT INTO cards (name) VALUES (?1) ON CONFLICT(name) DO NOTHING")?;
+    let mut card_id = db.prepare_cached("SELECT id FROM cards WHERE name = ?1")?;
+    let mut insert_combo_card = db.prepare_cached(
+        "INSERT OR IGNORE INTO combo_cards (combo_id, card_id, position, zones, must_be_commander)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for (position, card) in combo.cards.iter().enumerate() {
+        insert_card.execute([card])?;
+        let card_id: i64 = card_id.query_row([card], |row| row.get(0))?;
+        let requirement = combo.zones.iter().find(|zone| &zone.card == card);
+        let zones: String = requirement
+            .map(|requirement| requirement.zones.iter().map(|zone| zone.code()).collect())
+            .unwrap_or_default();
+        let must_be_commander = requirement
+            .map(|requirement| requirement.must_be_commander)
+            .unwrap_or_default();
+        insert_combo_card.execute((id, card_id, position as u32, zones, must_be_commander))?;
+    }
+
+    for result in combo.results.iter() {
+        db.execute(
+            "INSERT OR IGNORE INTO combo_results (combo_id, result) VALUES (?1, ?2)",
+            (id, result),
+        )?;
+    }
+    for format in legal_formats {
+        db.execute(
+            "INSERT INTO combo_legalities (combo_id, format) VALUES (?1, ?2)",
+            (id, format.keyword()),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Rebuilds a stored combo.
+fn read_combo(db: &rusqlite::Connection, id: &str) -> rusqlite::Result<Combo> {
+    let (url, identity, prerequisites, steps): (Option<String>, String, String, String) = db
+        .query_row(
+            "SELECT url, identity, prerequisites, steps FROM combos WHERE id = ?1",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+
+    let mut cards = vec![];
+    let mut zones = vec![];
+    let mut stmt = db.prepare_cached(
+        "SELECT k.name, cc.zones, cc.must_be_commander FROM combo_cards cc
+            JOIN cards k ON k.id = cc.card_id
+            WHERE cc.combo_id = ?1 ORDER BY cc.position",
+    )?;
+    let rows = stmt.query_map([id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, bool>(2)?,
+        ))
+    })?;
+    for row in rows {
+        let (card, zone_codes, must_be_commander) = row?;
+        zones.push(ZoneRequirement {
+            card: card.clone(),
+            zones: zone_codes
+                .chars()
+                .filter_map(|code| Zone::from_code(&code.to_string()))
+                .collect(),
+            must_be_commander,
+        });
+        cards.push(card);
+    }
+
+    let mut stmt =
+        db.prepare_cached("SELECT result FROM combo_results WHERE combo_id = ?1 ORDER BY rowid")?;
+    let results = stmt
+        .query_map([id], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    let mut stmt = db.prepare_cached("SELECT format FROM combo_legalities WHERE combo_id = ?1")?;
+    let mut legal_formats = vec![];
+    for keyword in stmt.query_map([id], |row| row.get::<_, String>(0))? {
+        legal_formats.extend(Format::from_keyword(&keyword?));
+    }
+
+    let lines = |text: String| -> Vec<String> {
+        text.lines()
+            .map(|line| line.to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    };
+
+    Ok(Combo {
+        id: Some(id.to_string()),
+        url,
+        cards,
+        zones,
+        prerequisites: lines(prerequisites),
+        steps: lines(steps),
+        results,
+        // Stored empty when the sources didn't say
+        color_identity: Some(identity)
+            .filter(|identity| !identity.is_empty())
+            .and_then(|identity| identity.parse().ok()),
+        sources: vec![],
+        legal_formats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_a_database_between_connections() {
+        let path =
+            std::env::temp_dir().join(format!("ccb-storage-{}.sqlite", uuid::Uuid::new_v4()));
+        let store = SqliteStore::open(&path).unwrap();
+
+        let mode: String = store
+            .connection()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!("wal", mode);
+
+        // Several threads writing at once each get a connection and none of them fail
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let store = store.clone();
+                scope.spawn(move || {
+                    for page in 0..20 {
+                        store
+                            .connection()
+                            .unwrap()
+                            .execute(
+                                "INSERT INTO html_page (url, html_body) VALUES (?1, 'body')",
+                                [format!("https://example.com/{thread}/{page}")],
+                            )
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        let count: u32 = store
+            .connection()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM html_page", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(160, count);
+
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[test]
+    fn compresses_pages_cached_before_compression() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let body = "<div class=\"combo\">Sol Ring</div>\n".repeat(200);
+        for url in ["https://edhrec.com/a", "https://edhrec.com/b"] {
+            store
+                .connection()
+                .unwrap()
+                .execute(
+                    "INSERT INTO html_page (url, html_body, status) VALUES (?1, ?2, 200)",
+                    (url, &body),
+                )
+                .unwrap();
+        }
+        let format = |url: &str| -> (i32, String) {
+            store
+                .connection()
+                .unwrap()
+                .query_row(
+                    "SELECT body_format, html_body FROM html_page WHERE url = ?1",
+                    [url],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap()
+        };
+        assert_eq!((0, body.clone()), format("https://edhrec.com/a"));
+
+        // Reading a page gives its text and compresses it on the way
+        let page = store.cached_page("https://edhrec.com/a").unwrap().unwrap();
+        assert_eq!(body, page.html_body);
+        assert_eq!((1, String::new()), format("https://edhrec.com/a"));
+        assert_eq!(0, format("https://edhrec.com/b").0);
+        assert_eq!(
+            body,
+            store
+                .cached_page("https://edhrec.com/a")
+                .unwrap()
+                .unwrap()
+                .html_body
+        );
+
+        // Vacuuming compresses the rest
+        store.vacuum().unwrap();
+        assert_eq!(1, format("https://edhrec.com/b").0);
+        let stats = store.cache_stats().unwrap();
+        assert!(stats.page_bytes < body.len() as u64 / 10);
+        assert!(store
+            .pages()
+            .unwrap()
+            .iter()
+            .all(|page| page.html_body == body));
+    }
+
+    #[test]
+    fn imports_bulk_export_into_normalized_tables() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let storage = crate::storage::Storage::new(store.clone());
+        let path = Path::new("tests/fixtures/spellbook_bulk_variants.json");
+        crate::bulk_data::import(path, &storage).unwrap();
+        crate::bulk_data::import(path, &storage).unwrap();
+
+        let db = store.connection().unwrap();
+        let count = |sql: &str| -> u32 { db.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(4, count("SELECT COUNT(*) FROM combos"));
+        assert_eq!(7, count("SELECT COUNT(*) FROM cards"));
+        assert_eq!(9, count("SELECT COUNT(*) FROM combo_cards"));
+        assert_eq!(6, count("SELECT COUNT(*) FROM combo_results"));
+        assert_eq!(
+            3,
+            count("SELECT COUNT(*) FROM combo_legalities WHERE format = 'commander'")
+        );
+    }
+}
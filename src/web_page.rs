@@ -1,9 +1,147 @@
-use std::sync::Mutex;
+use std::{collections::HashMap, sync::OnceLock, time::Duration};
 
-use lazy_static::lazy_static;
+use crate::{
+    error::CrawlError,
+    fetch::{Request, Response},
+    robots::{self, Robots},
+    sources, spellbook_api,
+    storage::{now, Storage},
+};
 
-lazy_static! {
-    static ref DBLOCK: Mutex<i32> = Mutex::new(0i32);
+/// Decides whether a fetched page is worth caching, returning why not.
+pub type PageCheck = fn(&WebPage) -> Result<(), String>;
+
+/// How a fetch uses the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Uses cached pages until they expire.
+    #[default]
+    Fresh,
+    /// Checks every cached page with the server, even ones that haven't expired.
+    Refresh,
+    /// Never touches the network. Cached pages are used even once they've expired, and
+    /// pages that aren't cached fail with `CrawlError::NotCached`.
+    Offline,
+}
+
+/// How long cached pages are used before they're checked with the server again.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Url patterns, where `*` matches anything and `$` anchors the end, with how long
+    /// pages matching them last. The first matching pattern wins.
+    pub ttls: Vec<(String, Duration)>,
+    pub default_ttl: Duration,
+    /// How long the combos saved from a search are reused for the same query.
+    pub search_ttl: Duration,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            // New combos show up in the API first, the rendered pages and EDHREC change less often.
+            // Sites can change their robots.txt, so it's checked again every day too.
+            ttls: vec![
+                (
+                    "*/robots.txt$".to_string(),
+                    Duration::from_secs(24 * 60 * 60),
+                ),
+                (
+                    format!("{}/*", spellbook_api::SPELLBOOK_API_URL),
+                    Duration::from_secs(24 * 60 * 60),
+                ),
+            ],
+            default_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            search_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+impl CacheConfig {
+    pub fn ttl_for(&self, url: &str) -> Duration {
+        self.ttls
+            .iter()
+            .find(|(pattern, _)| robots::pattern_matches(pattern, url))
+            .map(|(_, ttl)| *ttl)
+            .unwrap_or(self.default_ttl)
+    }
+}
+
+static CACHE_CONFIG: OnceLock<CacheConfig> = OnceLock::new();
+
+/// Sets how long cached pages last. Must be called before the first fetch.
+pub fn configure(config: CacheConfig) -> Result<(), String> {
+    CACHE_CONFIG
+        .set(config)
+        .map_err(|_| "The page cache has already been used".to_string())
+}
+
+pub fn cache_config() -> &'static CacheConfig {
+    CACHE_CONFIG.get_or_init(CacheConfig::default)
+}
+
+/// Reads a time to live such as "90s", "30m", "12h" or "7d". A bare number is seconds.
+pub fn parse_ttl(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid time to live: {text}"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid time to live: {text}")),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// The checks every page has to pass before it's cached: a 200, a content type we can
+/// read, and not an error or bot challenge page served in place of the real one.
+pub fn check_page(page: &WebPage) -> Result<(), String> {
+    // Pages cached before the status was recorded don't have one, the other checks still apply
+    if let Some(status) = page.status {
+        if status != 200 {
+            return Err(format!("HTTP {status}"));
+        }
+    }
+    if let Some(content_type) = page.content_type.as_deref() {
+        if !content_type.starts_with("text/html") && !content_type.starts_with("application/json") {
+            return Err(format!("unexpected content type {content_type}"));
+        }
+    }
+    if page.html_body.trim().is_empty() {
+        return Err("empty page".to_string());
+    }
+    // Cloudflare answers with a 200 and a "Just a moment..." page when it wants a browser
+    if page.html_body.contains("cf-chl") || page.html_body.contains("<title>Just a moment...") {
+        return Err("bot challenge page".to_string());
+    }
+    Ok(())
+}
+
+/// Counts cache hits for `cache stats`. Failing to count one shouldn't fail the fetch.
+fn record_lookups(storage: &Storage, hits: usize, misses: usize) {
+    if hits + misses == 0 {
+        return;
+    }
+    if let Err(e) = storage.record_lookups(hits as u64, misses as u64) {
+        log::warn!("Couldn't count cache lookups: {e}");
+    }
+}
+
+/// Picks the checks for a page by where it came from.
+pub fn check_for(url: &str) -> PageCheck {
+    if url.starts_with(spellbook_api::SPELLBOOK_API_URL) {
+        spellbook_api::check_variant_page
+    } else if url.starts_with(sources::SPELLBOOK_SEARCH_URL) {
+        sources::check_search_page
+    } else if url.ends_with("/robots.txt") {
+        robots::check_robots_page
+    } else {
+        check_page
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -11,52 +149,530 @@ pub struct WebPage {
     pub id: i32,
     pub url: String,
     pub html_body: String,
+    /// `None` for pages cached before the status was recorded.
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    /// When the page was last fetched or revalidated, in seconds since the Unix epoch.
+    /// `None` for pages cached before fetch times were recorded, which count as expired.
+    pub fetched_at: Option<i64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 impl WebPage {
-    pub fn fetch(url: &str) -> Self {
-        // Wrap the lock in this block, so that it is released when the block ends.
-        // Because we'll fetch at the end of the block, the lock will be released.
+    pub fn fetch(storage: &Storage, url: &str, mode: CacheMode) -> Result<Self, CrawlError> {
+        Self::fetch_all(storage, &[url.to_string()], mode).remove(0)
+    }
+
+    /// Fetches several pages at once through the shared scheduler, returning them in the same order.
+    /// Cached pages aren't fetched again until they expire.
+    pub fn fetch_all(
+        storage: &Storage,
+        urls: &[String],
+        mode: CacheMode,
+    ) -> Vec<Result<Self, CrawlError>> {
+        Self::fetch_all_checked(storage, urls, mode, |page| check_for(&page.url)(page))
+    }
+
+    /// Like `fetch_all`, but only caches pages that pass `check`. Cached pages that fail it
+    /// are fetched again, and fetched pages that fail it are returned as `CrawlError::Invalid`.
+    pub fn fetch_all_checked(
+        storage: &Storage,
+        urls: &[String],
+        mode: CacheMode,
+        check: impl Fn(&WebPage) -> Result<(), String>,
+    ) -> Vec<Result<Self, CrawlError>> {
+        let cached: Vec<Result<Option<Self>, CrawlError>> = urls
+            .iter()
+            .map(|url| Ok(storage.cached_page(url)?.filter(|page| check(page).is_ok())))
+            .collect();
+        if mode == CacheMode::Offline {
+            let hits = cached
+                .iter()
+                .filter(|page| matches!(page, Ok(Some(_))))
+                .count();
+            record_lookups(storage, hits, urls.len() - hits);
+            return urls
+                .iter()
+                .zip(cached)
+                .map(|(url, page)| page?.ok_or_else(|| CrawlError::NotCached { url: url.clone() }))
+                .collect();
+        }
+
+        // Expired pages are fetched again, conditionally so that unchanged ones aren't sent again
+        let now = now();
+        let stale: Vec<bool> = cached
+            .iter()
+            .map(|page| match page {
+                Ok(Some(page)) => {
+                    mode == CacheMode::Refresh
+                        || !page.is_fresh(cache_config().ttl_for(&page.url), now)
+                }
+                Ok(None) => true,
+                Err(_) => false,
+            })
+            .collect();
+        // Pages the site's robots.txt disallows aren't fetched
+        let mut sites = HashMap::new();
+        let blocked: Vec<Option<CrawlError>> = urls
+            .iter()
+            .zip(stale.iter())
+            .map(|(url, stale)| match storage.scheduler().robots_agent() {
+                Some(user_agent) if *stale => {
+                    Self::check_robots(storage, url, user_agent, &mut sites).err()
+                }
+                _ => None,
+            })
+            .collect();
+        let requests: Vec<Request> = urls
+            .iter()
+            .zip(cached.iter())
+            .zip(stale.iter())
+            .zip(blocked.iter())
+            .filter(|((_, stale), blocked)| **stale && blocked.is_none())
+            .map(|(((url, page), _), _)| match page {
+                Ok(Some(page)) => Request {
+                    url: url.clone(),
+                    etag: page.etag.clone(),
+                    last_modified: page.last_modified.clone(),
+                },
+                _ => Request::get(url),
+            })
+            .collect();
+
+        // No connection is held while fetching, each page is written in its own short transaction.
+        // Other searches asking for these pages get the same responses until they're stored.
+        let (responses, _holds) = storage.scheduler().send_all_holding(requests);
+        let mut responses = responses.into_iter();
+
+        // Pages the site said hadn't changed count as served from the cache
+        let (mut hits, mut misses) = (0, 0);
+        let pages = urls
+            .iter()
+            .zip(cached)
+            .zip(stale)
+            .zip(blocked)
+            .map(|(((url, page), stale), blocked)| {
+                if let Some(error) = blocked {
+                    return Err(error);
+                }
+                if !stale {
+                    hits += 1;
+                    return page?
+                        .ok_or_else(|| CrawlError::Database(format!("{url} wasn't cached")));
+                }
+                let response = responses.next().unwrap_or(Err(CrawlError::Shutdown));
+                match response.as_ref() {
+                    Ok(response) if response.not_modified() => hits += 1,
+                    _ => misses += 1,
+                }
+                Self::receive(storage, url, page.ok().flatten(), response?, &check)
+            })
+            .collect();
+        record_lookups(storage, hits, misses);
+        pages
+    }
+
+    /// Fails with `CrawlError::Disallowed` if the site's robots.txt disallows `url` for
+    /// `user_agent`. Each site's rules are looked up once and kept in `sites`.
+    fn check_robots(
+        storage: &Storage,
+        url: &str,
+        user_agent: &str,
+        sites: &mut HashMap<String, Result<Robots, CrawlError>>,
+    ) -> Result<(), CrawlError> {
+        // Urls that don't parse fail when they're fetched instead
+        let Ok(parsed) = reqwest::Url::parse(url) else {
+            return Ok(());
+        };
+        if parsed.path() == "/robots.txt" {
+            return Ok(());
+        }
+        let mut robots_url = parsed.clone();
+        robots_url.set_path("/robots.txt");
+        robots_url.set_query(None);
+        robots_url.set_fragment(None);
+        let robots = sites
+            .entry(robots_url.to_string())
+            .or_insert_with_key(|robots_url| Self::robots(storage, robots_url, user_agent))
+            .clone()?;
+
+        let path = match parsed.query() {
+            Some(query) => format!("{}?{query}", parsed.path()),
+            None => parsed.path().to_string(),
+        };
+        if robots.allows(&path) {
+            Ok(())
+        } else {
+            Err(CrawlError::Disallowed {
+                url: url.to_string(),
+            })
+        }
+    }
+
+    /// Reads a site's robots.txt through the cache. A site without one allows everything,
+    /// but one that can't be fetched fails, rather than being taken as allowing everything.
+    fn robots(storage: &Storage, robots_url: &str, user_agent: &str) -> Result<Robots, CrawlError> {
+        let page = match Self::fetch_all_checked(
+            storage,
+            &[robots_url.to_string()],
+            CacheMode::Fresh,
+            robots::check_robots_page,
+        )
+        .remove(0)
         {
-            #[allow(unused_variables)]
-            let lock = DBLOCK.lock().unwrap();
-
-            // Pull from db
-            let db = rusqlite::Connection::open("ccb.sqlite").unwrap();
-            let mut stmt = db
-                .prepare("SELECT id, url, html_body FROM html_page where url = (?1)")
-                .unwrap();
-
-            let rows = stmt
-                .query_map([url], |row| {
-                    Ok(Self {
-                        id: row.get(0).unwrap(),
-                        url: row.get(1).unwrap(),
-                        html_body: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-
-            for row in rows {
-                return row.unwrap();
+            Ok(page) => page,
+            Err(CrawlError::Status { url, status }) => {
+                let page = Self {
+                    id: 0,
+                    url,
+                    html_body: String::new(),
+                    status: Some(status),
+                    content_type: None,
+                    fetched_at: Some(now()),
+                    etag: None,
+                    last_modified: None,
+                };
+                robots::check_robots_page(&page).map_err(|_| CrawlError::Status {
+                    url: page.url.clone(),
+                    status,
+                })?;
+                storage.store_page(&page)?;
+                page
             }
+            Err(error) => return Err(error),
+        };
+        if page.status.is_some_and(|status| status != 200) {
+            return Ok(Robots::allow_all());
+        }
+        Ok(Robots::parse(&page.html_body, user_agent))
+    }
 
-            let body;
-
-            let client = reqwest::blocking::Client::new();
-            let response = client.get(url).send().unwrap();
-            body = response.text().unwrap();
+    /// Returns true if the page was fetched less than `ttl` before `now`.
+    pub fn is_fresh(&self, ttl: Duration, now: i64) -> bool {
+        self.fetched_at
+            .is_some_and(|fetched_at| now.saturating_sub(fetched_at) < ttl.as_secs() as i64)
+    }
 
-            db.execute(
-                "INSERT INTO html_page (url, html_body) VALUES (?1, ?2)",
-                [url.to_string(), body],
-            )
-            .unwrap();
+    /// Caches a fetched page, or marks the cached copy as current again if the server says it hasn't changed.
+    fn receive(
+        storage: &Storage,
+        url: &str,
+        cached: Option<Self>,
+        response: Response,
+        check: impl Fn(&WebPage) -> Result<(), String>,
+    ) -> Result<Self, CrawlError> {
+        if let (true, Some(mut page)) = (response.not_modified(), cached) {
+            let fetched_at = now();
+            storage.touch_page(page.id, fetched_at)?;
+            page.fetched_at = Some(fetched_at);
+            return Ok(page);
         }
 
-        return Self::fetch(url);
+        let mut page = Self {
+            id: 0,
+            url: url.to_string(),
+            html_body: response.body,
+            status: Some(response.status),
+            content_type: response.content_type,
+            fetched_at: Some(now()),
+            etag: response.etag,
+            last_modified: response.last_modified,
+        };
+        check(&page).map_err(|reason| CrawlError::Invalid {
+            url: url.to_string(),
+            reason,
+        })?;
+        page.id = storage.store_page(&page)?;
+        Ok(page)
     }
 
     pub fn document(&self) -> scraper::Html {
         scraper::Html::parse_document(&self.html_body)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch;
+
+    fn page(url: &str, status: Option<u16>, content_type: Option<&str>, body: &str) -> WebPage {
+        WebPage {
+            id: 0,
+            url: url.to_string(),
+            html_body: body.to_string(),
+            status,
+            content_type: content_type.map(|content_type| content_type.to_string()),
+            fetched_at: None,
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn rejects_error_and_challenge_pages() {
+        let html = Some("text/html; charset=utf-8");
+        let url = "https://edhrec.com/combos/sol-ring";
+        assert_eq!(
+            Ok(()),
+            check_page(&page(url, Some(200), html, "<p>combos</p>"))
+        );
+        assert_eq!(Ok(()), check_page(&page(url, None, None, "<p>combos</p>")));
+        assert_eq!(
+            Err("HTTP 203".to_string()),
+            check_page(&page(url, Some(203), html, "<p>combos</p>"))
+        );
+        assert!(check_page(&page(url, Some(200), Some("image/png"), "PNG")).is_err());
+        assert!(check_page(&page(url, Some(200), html, "  ")).is_err());
+        assert!(check_page(&page(
+            url,
+            Some(200),
+            html,
+            "<html><head><title>Just a moment...</title></head></html>"
+        ))
+        .is_err());
+
+        // The API has to answer with a page of variants
+        let api_url = format!("{}/variants/?q=ci%3Ac", spellbook_api::SPELLBOOK_API_URL);
+        let json = Some("application/json");
+        let variants = |body: &str| check_for(&api_url)(&page(&api_url, Some(200), json, body));
+        assert!(variants("{\"count\": 0, \"results\": []}").is_ok());
+        assert!(variants("{\"detail\": \"Throttled\"}").is_err());
+        assert!(variants("{\"count\": 3, \"results\": [{\"id\": ").is_err());
+    }
+
+    #[test]
+    fn caches_and_revalidates_pages() {
+        let (base_url, requests) = fetch::tests::scripted_server(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nETag: \"v1\"\r\nContent-Length: 9\r\nConnection: close\r\n\r\n<p>v1</p>",
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let storage = Storage::open_in_memory().unwrap();
+        let url = format!("{base_url}/page");
+
+        let first = WebPage::fetch(&storage, &url, CacheMode::Fresh).unwrap();
+        assert_eq!("<p>v1</p>", first.html_body);
+        assert_eq!(Some("\"v1\"".to_string()), first.etag);
+
+        // Fresh pages come from the cache
+        let second = WebPage::fetch(&storage, &url, CacheMode::Fresh).unwrap();
+        assert_eq!(first.id, second.id);
+        assert_eq!(1, requests.lock().unwrap().len());
+
+        // A refresh asks whether the page changed, and keeps the cached copy when it hasn't
+        let third = WebPage::fetch(&storage, &url, CacheMode::Refresh).unwrap();
+        assert_eq!("<p>v1</p>", third.html_body);
+        assert!(requests.lock().unwrap()[1]
+            .to_lowercase()
+            .contains("if-none-match: \"v1\""));
+
+        // Errors aren't cached
+        let missing = format!("{base_url}/missing");
+        assert!(WebPage::fetch(&storage, &missing, CacheMode::Fresh).is_err());
+        assert!(storage.cached_page(&missing).unwrap().is_none());
+    }
+
+    #[test]
+    fn expires_pages_by_url_pattern() {
+        let config = CacheConfig {
+            ttls: vec![
+                (
+                    "https://edhrec.com/*".to_string(),
+                    parse_ttl("12h").unwrap(),
+                ),
+                ("*.json$".to_string(), parse_ttl("30m").unwrap()),
+            ],
+            default_ttl: parse_ttl("7d").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Duration::from_secs(12 * 60 * 60),
+            config.ttl_for("https://edhrec.com/combos/sol-ring")
+        );
+        assert_eq!(
+            Duration::from_secs(30 * 60),
+            config.ttl_for("https://example.com/combos.json")
+        );
+        assert_eq!(
+            Duration::from_secs(7 * 24 * 60 * 60),
+            config.ttl_for("https://commanderspellbook.com/search/?q=ci%3Ac")
+        );
+        assert_eq!(Ok(Duration::from_secs(90)), parse_ttl("90"));
+        assert!(parse_ttl("soon").is_err());
+        assert!(parse_ttl("3w").is_err());
+
+        let mut cached = page("https://edhrec.com/", Some(200), None, "<p>combos</p>");
+        let ttl = Duration::from_secs(60);
+        assert!(!cached.is_fresh(ttl, 1000));
+        cached.fetched_at = Some(1000);
+        assert!(cached.is_fresh(ttl, 1059));
+        assert!(!cached.is_fresh(ttl, 1060));
+    }
+
+    #[test]
+    fn stores_validators_and_fetch_times() {
+        let storage = Storage::open_in_memory().unwrap();
+        let mut stored = page("https://edhrec.com/", Some(200), None, "<p>combos</p>");
+        stored.fetched_at = Some(1000);
+        stored.etag = Some("\"v1\"".to_string());
+        stored.last_modified = Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string());
+        stored.id = storage.store_page(&stored).unwrap();
+        storage.touch_page(stored.id, 2000).unwrap();
+
+        let cached = storage.cached_page(&stored.url).unwrap().unwrap();
+        assert_eq!(Some(2000), cached.fetched_at);
+        assert_eq!(stored.etag, cached.etag);
+        assert_eq!(stored.last_modified, cached.last_modified);
+
+        // Storing the page again replaces it
+        stored.html_body = "<p>more combos</p>".to_string();
+        storage.store_page(&stored).unwrap();
+        let cached = storage.cached_page(&stored.url).unwrap().unwrap();
+        assert_eq!("<p>more combos</p>", cached.html_body);
+    }
+
+    #[test]
+    fn offline_fetches_only_use_the_cache() {
+        let storage = Storage::open_in_memory().unwrap();
+        // Long expired, but still good enough when there's no network
+        let mut stored = page("https://edhrec.com/", Some(200), None, "<p>combos</p>");
+        stored.fetched_at = Some(1000);
+        storage.store_page(&stored).unwrap();
+
+        let cached = WebPage::fetch(&storage, &stored.url, CacheMode::Offline).unwrap();
+        assert_eq!("<p>combos</p>", cached.html_body);
+        assert_eq!(Some(1000), cached.fetched_at);
+
+        let missing = "https://edhrec.com/combos/sol-ring".to_string();
+        assert_eq!(
+            Some(CrawlError::NotCached {
+                url: missing.clone()
+            }),
+            WebPage::fetch(&storage, &missing, CacheMode::Offline).err()
+        );
+    }
+
+    #[test]
+    fn overlapping_searches_fetch_each_page_once() {
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let storage = {
+            let sent = sent.clone();
+            Storage::open_in_memory().unwrap().with_scheduler(
+                fetch::FetchScheduler::with_transport(4, move |request: &Request| {
+                    sent.lock().unwrap().push(request.url.clone());
+                    std::thread::sleep(Duration::from_millis(200));
+                    Ok(Response::ok(&format!("<p>{}</p>", request.url)))
+                }),
+            )
+        };
+        let urls: Vec<String> = ["sol-ring", "mana-crypt", "mana-vault"]
+            .map(|card| format!("https://edhrec.com/combos/{card}"))
+            .to_vec();
+
+        // Every search misses the cache, but only the first one's requests are sent
+        let barrier = std::sync::Barrier::new(6);
+        std::thread::scope(|scope| {
+            for _ in 0..6 {
+                let (storage, urls, barrier) = (&storage, &urls, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    for (url, page) in
+                        urls.iter()
+                            .zip(WebPage::fetch_all(storage, urls, CacheMode::Fresh))
+                    {
+                        assert_eq!(format!("<p>{url}</p>"), page.unwrap().html_body);
+                    }
+                });
+            }
+        });
+        let mut sent_urls = sent.lock().unwrap().clone();
+        sent_urls.sort();
+        let mut expected = urls.clone();
+        expected.sort();
+        assert_eq!(expected, sent_urls);
+
+        // After that they're cached
+        assert!(WebPage::fetch_all(&storage, &urls, CacheMode::Fresh)
+            .iter()
+            .all(|page| page.is_ok()));
+        assert_eq!(3, sent.lock().unwrap().len());
+    }
+
+    #[test]
+    fn caches_robots_txt_and_skips_disallowed_pages() {
+        // Each robots.txt answers with the next of its host's responses, other pages always work
+        let robots_txt = std::sync::Arc::new(std::sync::Mutex::new(HashMap::from([
+            (
+                "https://edhrec.com/robots.txt".to_string(),
+                vec![
+                    Ok(Response::ok("User-agent: *\nDisallow: /private/\n")),
+                    Err(CrawlError::Network {
+                        url: "https://edhrec.com/robots.txt".to_string(),
+                        message: "connection reset".to_string(),
+                    }),
+                ],
+            ),
+            (
+                "https://example.com/robots.txt".to_string(),
+                vec![Err(CrawlError::Status {
+                    url: "https://example.com/robots.txt".to_string(),
+                    status: 404,
+                })],
+            ),
+        ])));
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let scheduler = || {
+            let (robots_txt, sent) = (robots_txt.clone(), sent.clone());
+            fetch::FetchScheduler::with_transport(2, move |request: &Request| {
+                sent.lock().unwrap().push(request.url.clone());
+                match robots_txt.lock().unwrap().get_mut(&request.url) {
+                    Some(responses) => responses.pop().unwrap(),
+                    None => Ok(Response::ok("<p>combos</p>")),
+                }
+            })
+            .checking_robots(Some("commander-combo-breaker/1.0".to_string()))
+        };
+        let storage = Storage::open_in_memory()
+            .unwrap()
+            .with_scheduler(scheduler());
+        let fetch = |storage: &Storage, url: &str| WebPage::fetch(storage, url, CacheMode::Fresh);
+
+        // A robots.txt that can't be fetched fails the page instead of allowing everything
+        assert!(matches!(
+            fetch(&storage, "https://edhrec.com/private/page"),
+            Err(CrawlError::Network { .. })
+        ));
+        assert!(storage
+            .cached_page("https://edhrec.com/robots.txt")
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            Err(CrawlError::Disallowed {
+                url: "https://edhrec.com/private/page".to_string()
+            }),
+            fetch(&storage, "https://edhrec.com/private/page").map(|page| page.url)
+        );
+        assert!(fetch(&storage, "https://edhrec.com/public/page").is_ok());
+
+        // A site without a robots.txt allows everything
+        assert!(fetch(&storage, "https://example.com/private/page").is_ok());
+        assert!(fetch(&storage, "https://example.com/other/page").is_ok());
+        let robots_requests = |sent: &[String]| {
+            sent.iter()
+                .filter(|url| url.ends_with("/robots.txt"))
+                .count()
+        };
+        assert_eq!(3, robots_requests(&sent.lock().unwrap()));
+
+        // The rules are cached like any other page, so the next run doesn't fetch them again
+        let storage = storage.clone().with_scheduler(scheduler());
+        assert!(matches!(
+            fetch(&storage, "https://edhrec.com/private/other"),
+            Err(CrawlError::Disallowed { .. })
+        ));
+        assert!(fetch(&storage, "https://example.com/private/other").is_ok());
+        assert_eq!(3, robots_requests(&sent.lock().unwrap()));
+    }
+}